@@ -0,0 +1,159 @@
+// ブートローダからカーネルへ渡す情報
+// カーネルのエントリポイントには、この構造体へのポインタだけを渡す。
+// レイアウトを変更した場合は、BOOT_INFO_VERSIONを上げること。
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"MIKANBI\0");
+pub const BOOT_INFO_VERSION: u32 = 5;
+
+// カーネルのエントリポイントの型。呼び出し規約は、カーネル側のターゲットに合わせてSystem V
+pub type KernelEntryPoint = extern "sysv64" fn(boot_info: *const BootInfo) -> !;
+
+// ピクセルフォーマット。UEFIのEFI_GRAPHICS_PIXEL_FORMATと同じ値
+// 名前はMikanOSのPixelFormatに合わせている
+#[allow(non_camel_case_types)]
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    kPixelRGBResv8BitPerColor = 0,
+    kPixelBGRResv8BitPerColor,
+    kPixelBitMask,
+    kPixelBltOnly,
+}
+
+// pixel_formatがkPixelBitMaskのときの、32ビットのピクセル値の中での各色の位置
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PixelBitmask {
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameBufferConfig {
+    pub frame_buffer_base: u64,
+    pub frame_buffer_size: u64,
+    pub pixels_per_scan_line: u32,
+    pub horizontal_resolution: u32,
+    pub vertical_resolution: u32,
+    pub pixel_format: PixelFormat,
+    pub pixel_bitmask: PixelBitmask,
+}
+
+// UEFIのメモリマップ。descriptorはdescriptor_sizeごとに並んでいる
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryMapInfo {
+    pub buffer: u64,
+    pub map_size: u64,
+    pub descriptor_size: u64,
+    pub descriptor_version: u32,
+    _reserved: u32,
+}
+
+impl MemoryMapInfo {
+    pub fn new(buffer: u64, map_size: u64, descriptor_size: u64, descriptor_version: u32) -> Self {
+        MemoryMapInfo {
+            buffer,
+            map_size,
+            descriptor_size,
+            descriptor_version,
+            _reserved: 0,
+        }
+    }
+}
+
+// 物理メモリ上の領域。sizeが0のときは、存在しないことを示す
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryRange {
+    pub base: u64,
+    pub size: u64,
+}
+
+impl MemoryRange {
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
+    // この構造体のバイト数
+    pub size: u32,
+    pub frame_buffer: FrameBufferConfig,
+    pub memory_map: MemoryMapInfo,
+    // ACPI RSDPの物理アドレス。0のときは見つからなかったことを示す
+    pub acpi_rsdp: u64,
+    // SMBIOSのエントリポイント構造体(32ビット版と64ビット版)の物理アドレス。0のときは見つからなかったことを示す
+    pub smbios: u64,
+    pub smbios3: u64,
+    // カーネルコマンドライン(UTF-8, NULL終端)。sizeにはNULL文字を含まない
+    pub cmdline: MemoryRange,
+    pub initrd: MemoryRange,
+    // カーネルを展開した物理アドレスの範囲
+    pub kernel: MemoryRange,
+    // リンク時のアドレスと展開したアドレスの差
+    pub kernel_slide: u64,
+    // カーネルが自前の乱数生成器を初期化するための種。0のときは乱数が得られなかったことを示す
+    pub entropy_seed: u64,
+    // ブートローダのログ(UTF-8)。NULL終端ではない。カーネルはdmesgの先頭に入れる
+    pub boot_log: MemoryRange,
+}
+
+// BootInfo::from_ptrで検証に失敗した理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootInfoError {
+    // ポインタがNULLか、magicが一致しない
+    Invalid,
+    // versionかsizeが、このクレートのものと一致しない
+    UnsupportedVersion,
+}
+
+impl BootInfo {
+    pub fn new(frame_buffer: FrameBufferConfig) -> Self {
+        BootInfo {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: core::mem::size_of::<BootInfo>() as u32,
+            frame_buffer,
+            memory_map: MemoryMapInfo::default(),
+            acpi_rsdp: 0,
+            smbios: 0,
+            smbios3: 0,
+            cmdline: MemoryRange::default(),
+            initrd: MemoryRange::default(),
+            kernel: MemoryRange::default(),
+            kernel_slide: 0,
+            entropy_seed: 0,
+            boot_log: MemoryRange::default(),
+        }
+    }
+
+    // ブートローダから渡されたポインタを検証して、参照に変換する
+    // magicとversionを確認してから、他のフィールドを読むこと
+    /// # Safety
+    ///
+    /// ptrはNULLでなければ、BootInfoの境界に揃っていて、'staticの間ずっと読めるメモリを指していること。
+    /// エントリポイントにブートローダから渡されたポインタは、これを満たす。
+    pub unsafe fn from_ptr(ptr: *const BootInfo) -> Result<&'static BootInfo, BootInfoError> {
+        if ptr.is_null() {
+            return Err(BootInfoError::Invalid);
+        }
+        // magic, version, sizeは、どのバージョンでも先頭に置かれている
+        let magic = unsafe { core::ptr::addr_of!((*ptr).magic).read() };
+        if magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::Invalid);
+        }
+        let version = unsafe { core::ptr::addr_of!((*ptr).version).read() };
+        let size = unsafe { core::ptr::addr_of!((*ptr).size).read() };
+        if version != BOOT_INFO_VERSION || size as usize != core::mem::size_of::<BootInfo>() {
+            return Err(BootInfoError::UnsupportedVersion);
+        }
+        Ok(unsafe { &*ptr })
+    }
+}
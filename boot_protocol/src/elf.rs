@@ -0,0 +1,31 @@
+// カーネルのELFファイルを読むための定数
+// ブートローダは、これらの値を満たすELFファイルだけをカーネルとして読み込む
+
+// e_identの各バイトの位置と値
+pub const EI_NIDENT: usize = 16;
+pub const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+pub const EI_CLASS: usize = 4;
+pub const EI_DATA: usize = 5;
+pub const EI_VERSION: usize = 6;
+pub const ELF_CLASS_64: u8 = 2;
+pub const ELF_DATA_2LSB: u8 = 1;
+pub const EV_CURRENT: u8 = 1;
+
+// e_type
+pub const ET_EXEC: u16 = 2;
+// 位置独立実行形式(PIE)はET_DYNになる
+pub const ET_DYN: u16 = 3;
+// e_machine
+pub const EM_X86_64: u16 = 62;
+
+// ダイナミックセクションのタグ
+pub const DT_NULL: i64 = 0;
+pub const DT_RELA: i64 = 7;
+pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+
+// x86_64の再配置タイプ
+pub const R_X86_64_NONE: u32 = 0;
+pub const R_X86_64_RELATIVE: u32 = 8;
+
+pub const PAGE_SIZE: u64 = 0x1000;
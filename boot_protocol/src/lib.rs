@@ -0,0 +1,8 @@
+// ブートローダとカーネルの間の取り決め(ABI)
+// ブートローダが作ってカーネルが読む構造体や定数は、すべてここで定義する。
+// 両方のクレートがこのクレートを使うので、レイアウトの食い違いはコンパイルエラーになる。
+#![no_std]
+
+pub mod boot_info;
+pub mod elf;
+pub mod memory_map;
@@ -0,0 +1,146 @@
+// ブートローダから渡されるUEFIのメモリマップを読むためのモジュール
+use crate::boot_info::MemoryMapInfo;
+
+pub const UEFI_PAGE_SIZE: u64 = 4096;
+
+// UEFIのEFI_MEMORY_TYPE
+// ファームウェアによっては、OEM独自の値が入っていることがあるので、
+// ディスクリプタにはu32のまま保持して、必要なときにこのenumに変換する
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    Reserved = 0,
+    LoaderCode,
+    LoaderData,
+    BootServicesCode,
+    BootServicesData,
+    RuntimeServicesCode,
+    RuntimeServicesData,
+    ConventionalMemory,
+    UnusableMemory,
+    AcpiReclaimMemory,
+    AcpiMemoryNvs,
+    MemoryMappedIo,
+    MemoryMappedIoPortSpace,
+    PalCode,
+    PersistentMemory,
+}
+
+impl MemoryType {
+    pub fn from_u32(value: u32) -> Option<MemoryType> {
+        let memory_type = match value {
+            0 => MemoryType::Reserved,
+            1 => MemoryType::LoaderCode,
+            2 => MemoryType::LoaderData,
+            3 => MemoryType::BootServicesCode,
+            4 => MemoryType::BootServicesData,
+            5 => MemoryType::RuntimeServicesCode,
+            6 => MemoryType::RuntimeServicesData,
+            7 => MemoryType::ConventionalMemory,
+            8 => MemoryType::UnusableMemory,
+            9 => MemoryType::AcpiReclaimMemory,
+            10 => MemoryType::AcpiMemoryNvs,
+            11 => MemoryType::MemoryMappedIo,
+            12 => MemoryType::MemoryMappedIoPortSpace,
+            13 => MemoryType::PalCode,
+            14 => MemoryType::PersistentMemory,
+            _ => return None,
+        };
+        Some(memory_type)
+    }
+}
+
+// カーネルから見たメモリ領域の分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    // すぐに使える領域
+    Usable,
+    // ブートローダやUEFIが使っていた領域。中身(ブート情報やACPIテーブル、現在のスタックなど)を
+    // 使い終わった後であれば、空き領域として使える
+    Reclaimable,
+    // 使ってはいけない領域
+    Reserved,
+}
+
+// EFI_MEMORY_DESCRIPTOR
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryDescriptor {
+    pub memory_type: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+impl MemoryDescriptor {
+    pub fn memory_type(&self) -> Option<MemoryType> {
+        MemoryType::from_u32(self.memory_type)
+    }
+
+    pub fn kind(&self) -> MemoryRegionKind {
+        match self.memory_type() {
+            Some(MemoryType::ConventionalMemory) => MemoryRegionKind::Usable,
+            Some(
+                MemoryType::LoaderCode
+                | MemoryType::LoaderData
+                | MemoryType::BootServicesCode
+                | MemoryType::BootServicesData
+                | MemoryType::AcpiReclaimMemory,
+            ) => MemoryRegionKind::Reclaimable,
+            _ => MemoryRegionKind::Reserved,
+        }
+    }
+
+    pub fn physical_end(&self) -> u64 {
+        self.physical_start + self.size()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.number_of_pages * UEFI_PAGE_SIZE
+    }
+}
+
+// メモリマップの一行ずつを取得するイテレータ
+// ディスクリプタのサイズは、size_of::<MemoryDescriptor>()ではなく、descriptor_sizeを使う
+pub struct MemoryMapIterator<'a> {
+    map: &'a MemoryMapInfo,
+    offset: u64,
+}
+
+impl<'a> Iterator for MemoryMapIterator<'a> {
+    type Item = &'a MemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // descriptor_sizeが壊れていると、同じディスクリプタを返し続けたり、
+        // ディスクリプタの途中から読んだりするので、何も返さない
+        if self.map.descriptor_size < size_of::<MemoryDescriptor>() as u64 {
+            return None;
+        }
+        let end = self.offset.checked_add(self.map.descriptor_size)?;
+        if end > self.map.map_size {
+            None
+        } else {
+            let e = unsafe { &*((self.map.buffer + self.offset) as *const MemoryDescriptor) };
+            self.offset = end;
+            Some(e)
+        }
+    }
+}
+
+impl MemoryMapInfo {
+    pub fn iter(&self) -> MemoryMapIterator<'_> {
+        MemoryMapIterator {
+            map: self,
+            offset: 0,
+        }
+    }
+
+    // 指定した分類の領域の合計バイト数
+    pub fn total_bytes(&self, kind: MemoryRegionKind) -> u64 {
+        self.iter()
+            .filter(|desc| desc.kind() == kind)
+            .map(|desc| desc.size())
+            .sum()
+    }
+}
@@ -0,0 +1,30 @@
+// tools/hankaku.txtから、パニックや起動画面の表示に使うフォントを生成する
+// 形式はtools/makefont.pyと同じで、一文字が8x16ドットの16バイトになる
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const FONT_SOURCE: &str = "../tools/hankaku.txt";
+
+fn main() {
+    println!("cargo:rerun-if-changed={FONT_SOURCE}");
+    let source = fs::read_to_string(FONT_SOURCE).expect("failed to read hankaku.txt");
+
+    let mut font = Vec::new();
+    for line in source.lines() {
+        let bits: String = line
+            .chars()
+            .take_while(|c| matches!(c, '.' | '*' | '@'))
+            .collect();
+        if bits.is_empty() {
+            continue;
+        }
+        let row = bits
+            .chars()
+            .fold(0u8, |row, c| (row << 1) | if c == '.' { 0 } else { 1 });
+        font.push(row);
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("hankaku.bin"), font).expect("failed to write hankaku.bin");
+}
@@ -0,0 +1,106 @@
+// BMPファイルの読み込み
+// スプラッシュ画面のロゴに使う。無圧縮(BI_RGB)の24ビットと32ビットのものだけに対応する
+use crate::frame_buffer::Color;
+use crate::uefi::types::{Error, Result};
+
+// BITMAPFILEHEADER(14バイト)とBITMAPINFOHEADER(40バイト)の各フィールドの位置
+const FILE_HEADER_SIZE: usize = 14;
+const PIXEL_DATA_OFFSET: usize = 10;
+const INFO_HEADER_SIZE: usize = 14;
+const WIDTH: usize = 18;
+const HEIGHT: usize = 22;
+const BIT_COUNT: usize = 28;
+const COMPRESSION: usize = 30;
+const MIN_INFO_HEADER_SIZE: u32 = 40;
+
+const BI_RGB: u32 = 0;
+
+pub struct Bmp<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+    // 一行のバイト数。4バイト境界に揃えてある
+    stride: usize,
+    // trueなら上の行から順に並んでいる。BMPは通常、下の行から並んでいる
+    top_down: bool,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+impl<'a> Bmp<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Bmp<'a>> {
+        if data.len() < FILE_HEADER_SIZE + MIN_INFO_HEADER_SIZE as usize || &data[..2] != b"BM" {
+            return Err(Error::Failed("not a BMP file"));
+        }
+        if read_u32(data, INFO_HEADER_SIZE) < MIN_INFO_HEADER_SIZE {
+            return Err(Error::Failed("unsupported BMP header"));
+        }
+        let bytes_per_pixel = match read_u16(data, BIT_COUNT) {
+            24 => 3,
+            32 => 4,
+            _ => return Err(Error::Failed("unsupported BMP bit count")),
+        };
+        if read_u32(data, COMPRESSION) != BI_RGB {
+            return Err(Error::Failed("compressed BMP is not supported"));
+        }
+        let width = read_u32(data, WIDTH) as i32;
+        let height = read_u32(data, HEIGHT) as i32;
+        if width <= 0 || height == 0 {
+            return Err(Error::Failed("invalid BMP size"));
+        }
+        let width = width as usize;
+        let stride = (width * bytes_per_pixel).next_multiple_of(4);
+        let pixels = &data[(read_u32(data, PIXEL_DATA_OFFSET) as usize).min(data.len())..];
+        let rows = height.unsigned_abs() as usize;
+        if stride
+            .checked_mul(rows)
+            .is_none_or(|size| size > pixels.len())
+        {
+            return Err(Error::Failed("BMP pixel data is truncated"));
+        }
+        Ok(Bmp {
+            data: pixels,
+            width,
+            height: rows,
+            bytes_per_pixel,
+            stride,
+            top_down: height < 0,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // (x, y)は左上を原点とする
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let row = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+        let offset = row * self.stride + x * self.bytes_per_pixel;
+        // ピクセルはB, G, Rの順に並んでいる
+        Color::new(
+            self.data[offset + 2],
+            self.data[offset + 1],
+            self.data[offset],
+        )
+    }
+}
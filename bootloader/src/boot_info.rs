@@ -0,0 +1,52 @@
+// ブートローダからカーネルへ渡す情報
+// 構造体の定義はboot_protocolクレートにあり、カーネルと共有している。
+// ここでは、UEFIの構造体からの変換だけを定義する。
+use crate::uefi::graphics::{
+    EfiGraphicsOutputProtocolMode, EfiGraphicsPixelFormat, EfiPixelBitmask,
+};
+
+pub use boot_protocol::boot_info::*;
+
+impl From<EfiGraphicsPixelFormat> for PixelFormat {
+    fn from(format: EfiGraphicsPixelFormat) -> Self {
+        match format {
+            EfiGraphicsPixelFormat::PixelRedGreenBlueReserved8BitPerColor => {
+                PixelFormat::kPixelRGBResv8BitPerColor
+            }
+            EfiGraphicsPixelFormat::PixelBlueGreenRedReserved8BitPerColor => {
+                PixelFormat::kPixelBGRResv8BitPerColor
+            }
+            EfiGraphicsPixelFormat::PixelBitMask => PixelFormat::kPixelBitMask,
+            // PixelFormatMaxはモードの情報としては現れない
+            EfiGraphicsPixelFormat::PixelBltOnly | EfiGraphicsPixelFormat::PixelFormatMax => {
+                PixelFormat::kPixelBltOnly
+            }
+        }
+    }
+}
+
+impl From<EfiPixelBitmask> for PixelBitmask {
+    fn from(bitmask: EfiPixelBitmask) -> Self {
+        PixelBitmask {
+            red_mask: bitmask.red_mask,
+            green_mask: bitmask.green_mask,
+            blue_mask: bitmask.blue_mask,
+            reserved_mask: bitmask.reserved_mask,
+        }
+    }
+}
+
+// GOPの現在のモードから作る
+impl From<&EfiGraphicsOutputProtocolMode<'_>> for FrameBufferConfig {
+    fn from(mode: &EfiGraphicsOutputProtocolMode) -> Self {
+        FrameBufferConfig {
+            frame_buffer_base: mode.frame_buffer_base as u64,
+            frame_buffer_size: mode.frame_buffer_size as u64,
+            pixels_per_scan_line: mode.info.pixels_per_scan_line,
+            horizontal_resolution: mode.info.horizontal_resolution,
+            vertical_resolution: mode.info.vertical_resolution,
+            pixel_format: mode.info.pixel_format.into(),
+            pixel_bitmask: mode.info.pixel_information.into(),
+        }
+    }
+}
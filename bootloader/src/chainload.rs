@@ -0,0 +1,96 @@
+// 他のEFIアプリケーション(UEFIシェルや別のブートローダなど)を起動する
+// ブートボリューム上のファイルをLoadImageで読み込み、StartImageで実行する。
+// アプリケーションが終了したら、このブートローダに戻ってくる。
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+use crate::uefi::device_path::{EfiDevicePathProtocol, file_device_path};
+use crate::uefi::file::File;
+use crate::uefi::memory::EfiMemoryType;
+use crate::uefi::types::{EFI_FILE_MODE_READ, EfiHandle, EfiStatus, EfiVoid, Error, Result};
+use crate::uefi::{EfiBootServicesTable, EfiLoadedImageProtocol, EfiSystemTable};
+
+// rootにあるpathのEFIアプリケーションを起動し、終了したときの終了コードを返す
+// optionsは、アプリケーションに引数(LoadOptions)として渡す
+pub fn chainload(
+    image_handle: EfiHandle,
+    system_table: &EfiSystemTable,
+    root: &File,
+    path: &str,
+    options: &str,
+) -> Result<EfiStatus> {
+    let boot_services = system_table.boot_services;
+    let loaded_image =
+        boot_services.open_protocol::<EfiLoadedImageProtocol>(image_handle, image_handle)?;
+    let device_path = boot_services
+        .open_protocol::<EfiDevicePathProtocol>(loaded_image.device_handle, image_handle)?;
+    let file_path = file_device_path(boot_services, device_path, path)?;
+
+    let mut file = root.open(path, EFI_FILE_MODE_READ)?;
+    let image = file.read_to_end(boot_services, EfiMemoryType::LOADER_DATA)?;
+    drop(file);
+    // LoadImageはイメージをコピーするので、読み込んだファイルはすぐに解放してよい
+    let result = boot_services.load_image(image_handle, file_path.as_ptr(), image);
+    let _ = boot_services.free_pool(image.as_mut_ptr());
+    let child = result?;
+
+    let load_options = match set_load_options(boot_services, image_handle, child, path, options) {
+        Ok(load_options) => load_options,
+        Err(e) => {
+            let _ = boot_services.unload_image(child);
+            return Err(e);
+        }
+    };
+    let status = boot_services.start_image(child);
+    if !load_options.is_null() {
+        let _ = boot_services.free_pool(load_options);
+    }
+    Ok(status)
+}
+
+// 子イメージの引数を、"path options"の形のNULL終端のUCS-2で設定する
+// UEFIシェルなどは、最初の単語をアプリケーションの名前(argv[0])として扱うので、パスを先頭に置く
+// 返したバッファは、子イメージが終了するまで解放してはいけない。引数がなければNULLを返す
+fn set_load_options(
+    boot_services: &EfiBootServicesTable,
+    image_handle: EfiHandle,
+    child: EfiHandle,
+    path: &str,
+    options: &str,
+) -> Result<*mut EfiVoid> {
+    if options.is_empty() {
+        return Ok(null_mut());
+    }
+    let chars = path
+        .encode_utf16()
+        .chain(Some(b' ' as u16))
+        .chain(options.encode_utf16())
+        .chain(Some(0));
+    let size = chars.clone().count() * size_of::<u16>();
+    if size > u32::MAX as usize {
+        return Err(Error::Failed("load options are too long"));
+    }
+
+    let mut buffer = null_mut::<EfiVoid>();
+    boot_services
+        .allocate_pool(EfiMemoryType::LOADER_DATA, size, &mut buffer)
+        .into_result()?;
+    let bytes = unsafe { core::slice::from_raw_parts_mut(buffer, size) };
+    for (i, c) in chars.enumerate() {
+        bytes[i * 2..i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+    }
+
+    match boot_services.open_protocol_ptr::<EfiLoadedImageProtocol>(child, image_handle) {
+        Ok(child_image) => {
+            unsafe {
+                (*child_image).load_options = buffer;
+                (*child_image).load_options_size = size as u32;
+            }
+            Ok(buffer)
+        }
+        Err(e) => {
+            let _ = boot_services.free_pool(buffer);
+            Err(e)
+        }
+    }
+}
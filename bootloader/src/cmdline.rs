@@ -0,0 +1,83 @@
+// カーネルに渡すコマンドライン
+// UEFIシェルやブートオプションから渡された引数(LoadOptions)は、設定ファイルのcmdlineより優先する。
+// カーネルには、LOADER_DATAのプールに置いたNULL終端のUTF-8の文字列として渡す。
+use core::ptr::null_mut;
+
+use crate::boot_info::MemoryRange;
+use crate::uefi::EfiBootServicesTable;
+use crate::uefi::memory::EfiMemoryType;
+use crate::uefi::types::{EfiVoid, Result};
+
+// LoadOptionsをUTF-8に変換して、LOADER_DATAのプールに置く
+// 文字列として解釈できない場合や、引数が空の場合はNoneを返す
+pub fn load_options_to_cmdline(
+    boot_services: &EfiBootServicesTable,
+    load_options: &[u16],
+) -> Result<Option<&'static str>> {
+    let Some(options) = decode_len(load_options).map(|len| &load_options[..len]) else {
+        return Ok(None);
+    };
+    let utf8_len = char::decode_utf16(options.iter().copied())
+        .map(|c| c.map_or(0, char::len_utf8))
+        .sum();
+    if utf8_len == 0 {
+        return Ok(None);
+    }
+    let buffer = allocate(boot_services, utf8_len)?;
+    let mut len = 0;
+    for c in char::decode_utf16(options.iter().copied()).flatten() {
+        len += c.encode_utf8(&mut buffer[len..]).len();
+    }
+    // decode_lenで検証済みなので、必ずUTF-8として正しい
+    let text = core::str::from_utf8(&buffer[..len]).unwrap_or("");
+    let cmdline = skip_image_name(text).trim();
+    Ok(if cmdline.is_empty() {
+        None
+    } else {
+        Some(cmdline)
+    })
+}
+
+// cmdlineを終端のNULL文字付きでLOADER_DATAのプールにコピーし、その範囲を返す
+// sizeには終端のNULL文字を含まない
+pub fn copy_cmdline(boot_services: &EfiBootServicesTable, cmdline: &str) -> Result<MemoryRange> {
+    let buffer = allocate(boot_services, cmdline.len() + 1)?;
+    buffer[..cmdline.len()].copy_from_slice(cmdline.as_bytes());
+    buffer[cmdline.len()] = 0;
+    Ok(MemoryRange {
+        base: buffer.as_ptr() as u64,
+        size: cmdline.len() as u64,
+    })
+}
+
+// 最初のNULL文字までの長さ。制御文字や不正なサロゲートを含む場合は、文字列ではないとみなしてNone
+// ブートオプションによっては、LoadOptionsに任意のバイナリが入っていることがある
+fn decode_len(load_options: &[u16]) -> Option<usize> {
+    let len = load_options
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(load_options.len());
+    let is_text = char::decode_utf16(load_options[..len].iter().copied())
+        .all(|c| c.is_ok_and(|c| !c.is_control() || c.is_whitespace()));
+    is_text.then_some(len)
+}
+
+// UEFIシェルは、最初の引数としてイメージのファイル名を渡すので取り除く
+fn skip_image_name(text: &str) -> &str {
+    let text = text.trim_start();
+    let (first, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let is_image_name = first
+        .len()
+        .checked_sub(4)
+        .and_then(|start| first.get(start..))
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(".efi"));
+    if is_image_name { rest } else { text }
+}
+
+fn allocate(boot_services: &EfiBootServicesTable, size: usize) -> Result<&'static mut [u8]> {
+    let mut buffer = null_mut::<EfiVoid>();
+    boot_services
+        .allocate_pool(EfiMemoryType::LOADER_DATA, size, &mut buffer)
+        .into_result()?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(buffer, size) })
+}
@@ -0,0 +1,408 @@
+// ブートローダの設定
+// ブートボリュームの\EFI\mikanos\boot.cfgに、key=valueの形式で書く。
+// 空行と'#'で始まる行は無視する。ファイルがない場合は、すべてデフォルト値になる。
+//
+//   kernel=\kernel.elf
+//   resolution=1280x800
+//   cmdline=log=debug console=serial
+//   initrd=\initrd.tar
+//   log=info
+//   dump_memmap=yes
+//   memmap_format=csv
+//   memmap_path=\memmap.csv
+//   kaslr=no
+//   timeout=3
+//   splash=yes
+//   logo=\EFI\mikanos\logo.bmp
+//   entry=MikanOS;\kernel.elf;log=info
+//   entry=MikanOS (debug);\kernel.elf;log=debug
+//   chainload=UEFI Shell;\EFI\tools\shell.efi
+//
+// kernelは、ブートボリュームにあればそれを使い、なければ他のファイルシステムのボリュームを順に探す。
+// initrdは、カーネルが見つかったボリュームから読み込む。見つけたボリュームの一覧(デバイスパス)は、log=info以上で画面に表示する。
+// logは、画面に表示するログのレベル(error, warn, info, debug)。デフォルトはwarnで、警告とエラーだけを表示する。
+// splashをyesにすると、カーネルの読み込み中にロゴと進捗バーを表示する。その間、ログは画面に表示しないので、デフォルトはno。
+// logoは、スプラッシュ画面に表示するBMPファイル(無圧縮の24ビットか32ビット)。ファイルがなければ、進捗バーだけを表示する。
+// entryは、ブートメニューに表示する項目で、"タイトル;カーネルのパス;コマンドライン"の形式で書く。
+// カーネルのパスとコマンドラインを省略した場合は、kernelとcmdlineの値を使う。
+// entryが一つもない場合は、kernelとcmdlineから項目を一つ作り、先頭に置く。
+// chainloadは、カーネルの代わりに他のEFIアプリケーションを起動する項目で、"タイトル;EFIファイルのパス;引数"の形式で書く。
+// 起動したアプリケーションが終了すると、ブートメニューに戻る。
+use core::fmt;
+
+pub const CONFIG_PATH: &str = "\\EFI\\mikanos\\boot.cfg";
+// 設定ファイルとして読み込む最大のバイト数
+pub const CONFIG_FILE_MAX_SIZE: usize = 4096;
+
+pub const DEFAULT_KERNEL_PATH: &str = "\\kernel.elf";
+pub const DEFAULT_ENTRY_TITLE: &str = "MikanOS";
+// ブートメニューの待ち時間(秒)
+pub const DEFAULT_TIMEOUT: u32 = 3;
+pub const MAX_BOOT_ENTRIES: usize = 8;
+pub const DEFAULT_LOGO_PATH: &str = "\\EFI\\mikanos\\logo.bmp";
+
+// ブートローダが画面に出力するログの詳細さ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(s: &str) -> Option<LogLevel> {
+        let level = match s {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            _ => return None,
+        };
+        Some(level)
+    }
+}
+
+// メモリマップを書き出すときの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemmapFormat {
+    Csv,
+    Json,
+    // ヘッダの後に、ファームウェアから取得した記述子をそのまま並べる
+    Binary,
+}
+
+impl MemmapFormat {
+    pub fn parse(s: &str) -> Option<MemmapFormat> {
+        let format = match s {
+            "csv" => MemmapFormat::Csv,
+            "json" => MemmapFormat::Json,
+            "binary" => MemmapFormat::Binary,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    // memmap_pathを指定しなかったときの書き出し先
+    pub fn default_path(self) -> &'static str {
+        match self {
+            MemmapFormat::Csv => "\\memmap.csv",
+            MemmapFormat::Json => "\\memmap.json",
+            MemmapFormat::Binary => "\\memmap.bin",
+        }
+    }
+}
+
+// ブートメニューの項目の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootEntryKind {
+    // カーネルを読み込んで起動する
+    Kernel,
+    // 他のEFIアプリケーションを起動する
+    Chainload,
+}
+
+// ブートメニューの項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootEntry<'a> {
+    pub kind: BootEntryKind,
+    pub title: &'a str,
+    // Kernelのときはカーネルのパス、ChainloadのときはEFIファイルのパス
+    pub path: &'a str,
+    // Chainloadのときは、アプリケーションに渡す引数
+    pub cmdline: &'a str,
+    // cmdlineを省略したので、kernelの項目のcmdlineで埋めたか
+    // 起動時に渡された引数で置き換えるのは、この項目だけにする
+    cmdline_omitted: bool,
+}
+
+impl BootEntry<'_> {
+    const EMPTY: BootEntry<'static> = BootEntry {
+        kind: BootEntryKind::Kernel,
+        title: "",
+        path: "",
+        cmdline: "",
+        cmdline_omitted: false,
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BootConfig<'a> {
+    pub kernel_path: &'a str,
+    // 希望する画面の解像度(横, 縦)。Noneのときは、ブートローダが選ぶ
+    pub resolution: Option<(u32, u32)>,
+    pub cmdline: &'a str,
+    pub initrd_path: Option<&'a str>,
+    pub log_level: LogLevel,
+    // trueのとき、メモリマップをファイルに書き出す
+    pub dump_memmap: bool,
+    pub memmap_format: MemmapFormat,
+    // Noneのときは、memmap_formatに応じたデフォルトのパス
+    pub memmap_path: Option<&'a str>,
+    // trueのとき、位置独立なカーネルをランダムなアドレスに展開する
+    pub kaslr: bool,
+    // ブートメニューの待ち時間(秒)。0のときは、メニューを表示せずに最初の項目で起動する
+    pub timeout: u32,
+    // trueのとき、カーネルの読み込み中にスプラッシュ画面を表示する
+    pub splash: bool,
+    pub logo_path: &'a str,
+    entries: [BootEntry<'a>; MAX_BOOT_ENTRIES],
+    num_entries: usize,
+}
+
+impl Default for BootConfig<'_> {
+    fn default() -> Self {
+        let mut config = BootConfig {
+            kernel_path: DEFAULT_KERNEL_PATH,
+            resolution: None,
+            cmdline: "",
+            initrd_path: None,
+            log_level: LogLevel::Warn,
+            dump_memmap: true,
+            memmap_format: MemmapFormat::Csv,
+            memmap_path: None,
+            kaslr: false,
+            timeout: DEFAULT_TIMEOUT,
+            splash: false,
+            logo_path: DEFAULT_LOGO_PATH,
+            entries: [BootEntry::EMPTY; MAX_BOOT_ENTRIES],
+            num_entries: 0,
+        };
+        config.fill_entries();
+        config
+    }
+}
+
+// 設定ファイルの読み込み中に見つかった問題。該当する行は無視される
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigWarningKind<'a> {
+    // '='がない
+    MissingSeparator,
+    UnknownKey(&'a str),
+    InvalidValue { key: &'a str, value: &'a str },
+    TooManyEntries,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigWarning<'a> {
+    // 1始まりの行番号
+    pub line: usize,
+    pub kind: ConfigWarningKind<'a>,
+}
+
+impl fmt::Display for ConfigWarning<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "boot.cfg:{}: ", self.line)?;
+        match self.kind {
+            ConfigWarningKind::MissingSeparator => write!(f, "expected key=value"),
+            ConfigWarningKind::UnknownKey(key) => write!(f, "unknown key '{key}'"),
+            ConfigWarningKind::InvalidValue { key, value } => {
+                write!(f, "invalid value '{value}' for '{key}'")
+            }
+            ConfigWarningKind::TooManyEntries => {
+                write!(f, "too many entries (max {MAX_BOOT_ENTRIES})")
+            }
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "true" | "on" | "1" => Some(true),
+        "no" | "false" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// "1280x800"の形式
+fn parse_resolution(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    let width = width.trim().parse().ok()?;
+    let height = height.trim().parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+// "タイトル;パス;コマンドライン"の形式。Kernelの項目では、タイトル以外は省略できる
+// Chainloadの項目では、パスを省略できない
+fn parse_entry(kind: BootEntryKind, value: &str) -> Option<BootEntry<'_>> {
+    let mut fields = value.splitn(3, ';').map(str::trim);
+    let title = fields.next().filter(|title| !title.is_empty())?;
+    let entry = BootEntry {
+        kind,
+        title,
+        path: fields.next().unwrap_or(""),
+        cmdline: fields.next().unwrap_or(""),
+        cmdline_omitted: false,
+    };
+    if kind == BootEntryKind::Chainload && entry.path.is_empty() {
+        return None;
+    }
+    Some(entry)
+}
+
+impl<'a> BootConfig<'a> {
+    // 設定ファイルの内容を解釈する。問題のある行はwarnに渡して、読み飛ばす
+    pub fn parse(text: &'a str, mut warn: impl FnMut(ConfigWarning<'a>)) -> BootConfig<'a> {
+        let mut config = BootConfig {
+            num_entries: 0,
+            ..BootConfig::default()
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut warn_kind = |kind| warn(ConfigWarning { line: i + 1, kind });
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    warn_kind(ConfigWarningKind::MissingSeparator);
+                    continue;
+                }
+            };
+            if (key == "entry" || key == "chainload") && config.num_entries == MAX_BOOT_ENTRIES {
+                warn_kind(ConfigWarningKind::TooManyEntries);
+                continue;
+            }
+            if !config.set(key, value) {
+                if config.is_known_key(key) {
+                    warn_kind(ConfigWarningKind::InvalidValue { key, value });
+                } else {
+                    warn_kind(ConfigWarningKind::UnknownKey(key));
+                }
+            }
+        }
+        config.fill_entries();
+        config
+    }
+
+    // メモリマップの書き出し先
+    pub fn memmap_path(&self) -> &'a str {
+        self.memmap_path
+            .unwrap_or_else(|| self.memmap_format.default_path())
+    }
+
+    // ブートメニューの項目
+    pub fn entries(&self) -> &[BootEntry<'a>] {
+        &self.entries[..self.num_entries]
+    }
+
+    // UEFIシェルやブートオプションから引数が渡されたときに、設定ファイルのcmdlineより優先する
+    // 項目ごとにコマンドラインを書いたものは、そのまま使う
+    pub fn override_cmdline(&mut self, cmdline: &'a str) {
+        self.cmdline = cmdline;
+        for entry in self.entries[..self.num_entries].iter_mut() {
+            if entry.kind == BootEntryKind::Kernel && entry.cmdline_omitted {
+                entry.cmdline = cmdline;
+            }
+        }
+    }
+
+    // カーネルの項目で省略された値を、kernelとcmdlineの値で埋める
+    // カーネルの項目がなければ先頭に一つ作る。項目がいっぱいのときは、最後の項目を捨てる
+    fn fill_entries(&mut self) {
+        if !self
+            .entries()
+            .iter()
+            .any(|entry| entry.kind == BootEntryKind::Kernel)
+        {
+            let moved = self.num_entries.min(MAX_BOOT_ENTRIES - 1);
+            self.entries.copy_within(..moved, 1);
+            self.entries[0] = BootEntry {
+                title: DEFAULT_ENTRY_TITLE,
+                ..BootEntry::EMPTY
+            };
+            self.num_entries = moved + 1;
+        }
+        for entry in self.entries[..self.num_entries].iter_mut() {
+            if entry.kind != BootEntryKind::Kernel {
+                continue;
+            }
+            if entry.path.is_empty() {
+                entry.path = self.kernel_path;
+            }
+            if entry.cmdline.is_empty() {
+                entry.cmdline = self.cmdline;
+                entry.cmdline_omitted = true;
+            }
+        }
+    }
+
+    fn is_known_key(&self, key: &str) -> bool {
+        matches!(
+            key,
+            "kernel"
+                | "resolution"
+                | "cmdline"
+                | "initrd"
+                | "log"
+                | "dump_memmap"
+                | "memmap_format"
+                | "memmap_path"
+                | "kaslr"
+                | "timeout"
+                | "splash"
+                | "logo"
+                | "entry"
+                | "chainload"
+        )
+    }
+
+    // 値を設定する。キーが不明か、値が不正な場合はfalse
+    fn set(&mut self, key: &str, value: &'a str) -> bool {
+        match key {
+            "kernel" if !value.is_empty() => self.kernel_path = value,
+            "resolution" => match parse_resolution(value) {
+                Some(resolution) => self.resolution = Some(resolution),
+                None => return false,
+            },
+            "cmdline" => self.cmdline = value,
+            "initrd" => self.initrd_path = if value.is_empty() { None } else { Some(value) },
+            "log" => match LogLevel::parse(value) {
+                Some(level) => self.log_level = level,
+                None => return false,
+            },
+            "dump_memmap" => match parse_bool(value) {
+                Some(dump_memmap) => self.dump_memmap = dump_memmap,
+                None => return false,
+            },
+            "memmap_format" => match MemmapFormat::parse(value) {
+                Some(format) => self.memmap_format = format,
+                None => return false,
+            },
+            "memmap_path" => self.memmap_path = if value.is_empty() { None } else { Some(value) },
+            "kaslr" => match parse_bool(value) {
+                Some(kaslr) => self.kaslr = kaslr,
+                None => return false,
+            },
+            "timeout" => match value.parse() {
+                Ok(timeout) => self.timeout = timeout,
+                Err(_) => return false,
+            },
+            "splash" => match parse_bool(value) {
+                Some(splash) => self.splash = splash,
+                None => return false,
+            },
+            "logo" if !value.is_empty() => self.logo_path = value,
+            "entry" | "chainload" => {
+                let kind = if key == "entry" {
+                    BootEntryKind::Kernel
+                } else {
+                    BootEntryKind::Chainload
+                };
+                match parse_entry(kind, value) {
+                    Some(entry) => {
+                        self.entries[self.num_entries] = entry;
+                        self.num_entries += 1;
+                    }
+                    None => return false,
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
@@ -1,5 +1,10 @@
 // ELFヘッダーの内容を表現する構造体を定義
-const EI_NIDENT: usize = 16;
+use crate::uefi::EfiBootServicesTable;
+use crate::uefi::types::EfiStatus;
+use core::mem::size_of;
+
+// 定数は、カーネルと共有するためにboot_protocolで定義している
+pub use boot_protocol::elf::*;
 
 // Elf File Header
 #[repr(C)]
@@ -22,10 +27,11 @@ pub struct ElfEhdr {
 }
 
 //Elf Program Header
+// p_typeには、PT_GNU_STACKなどElfPhdrTypeにない値も入るので、u32のまま保持する
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct ElfPhdr {
-    pub p_type: ElfPhdrType,
+    pub p_type: u32,
     pub p_flags: u32,
     pub p_offset: u64,
     pub p_vaddr: u64,
@@ -35,6 +41,35 @@ pub struct ElfPhdr {
     pub p_align: u64,
 }
 
+impl ElfPhdr {
+    pub fn phdr_type(&self) -> Option<ElfPhdrType> {
+        ElfPhdrType::from_u32(self.p_type)
+    }
+}
+
+// PT_DYNAMICセグメントの一要素
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ElfDyn {
+    pub d_tag: i64,
+    pub d_val: u64,
+}
+
+// 加数付きの再配置エントリ
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ElfRela {
+    pub r_offset: u64,
+    pub r_info: u64,
+    pub r_addend: i64,
+}
+
+impl ElfRela {
+    pub fn r_type(&self) -> u32 {
+        (self.r_info & 0xffff_ffff) as u32
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u32)]
 pub enum ElfPhdrType {
@@ -47,3 +82,363 @@ pub enum ElfPhdrType {
     PtPhdr,
     PtTls,
 }
+
+impl ElfPhdrType {
+    pub fn from_u32(value: u32) -> Option<ElfPhdrType> {
+        let phdr_type = match value {
+            0 => ElfPhdrType::PtNull,
+            1 => ElfPhdrType::PtLoad,
+            2 => ElfPhdrType::PtDynamic,
+            3 => ElfPhdrType::PtInterp,
+            4 => ElfPhdrType::PtNote,
+            5 => ElfPhdrType::PtShlib,
+            6 => ElfPhdrType::PtPhdr,
+            7 => ElfPhdrType::PtTls,
+            _ => return None,
+        };
+        Some(phdr_type)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ElfError {
+    // ファイルがELFヘッダより小さい
+    TooSmall,
+    InvalidMagic,
+    UnsupportedClass(u8),
+    UnsupportedEndian(u8),
+    UnsupportedVersion(u8),
+    UnsupportedType(u16),
+    UnsupportedMachine(u16),
+    InvalidProgramHeaderSize(u16),
+    // プログラムヘッダの配列がファイルの外を指している
+    ProgramHeaderOutOfBounds,
+    // セグメントのファイル上の内容がファイルの外を指している
+    SegmentOutOfBounds,
+    // p_fileszがp_memszより大きい
+    InvalidSegmentSize,
+    // 仮想アドレスの計算がオーバーフローする
+    AddressOverflow,
+    NoLoadableSegment,
+    // エントリポイントが展開する範囲の外にある
+    EntryOutOfRange,
+    // PT_DYNAMICの内容が壊れている
+    InvalidDynamicSection,
+    UnsupportedRelocation(u32),
+    // 再配置の対象が展開した範囲の外にある
+    RelocationOutOfRange,
+    EfiError(EfiStatus),
+}
+
+impl From<EfiStatus> for ElfError {
+    fn from(e: EfiStatus) -> Self {
+        ElfError::EfiError(e)
+    }
+}
+
+impl ElfError {
+    // 画面に表示するためのEfiStatus。EfiStatusを持たないエラーはLoadErrorにする
+    pub fn status(&self) -> EfiStatus {
+        match self {
+            ElfError::EfiError(status) => *status,
+            _ => EfiStatus::LoadError,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, ElfError>;
+
+// メモリ上に読み込んだELFファイル
+// parseで検証を済ませているので、ヘッダやプログラムヘッダはファイルの範囲内にあることが保証される
+pub struct ElfImage<'a> {
+    data: &'a [u8],
+    header: ElfEhdr,
+}
+
+// 展開したイメージの情報
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedImage {
+    // 展開した物理アドレスの範囲 [start, end)。ページ境界に揃えてある
+    pub start: u64,
+    pub end: u64,
+    // 再配置後のエントリポイント
+    pub entry: u64,
+    // リンク時のアドレスと展開したアドレスの差
+    pub slide: u64,
+}
+
+impl<'a> ElfImage<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < size_of::<ElfEhdr>() {
+            return Err(ElfError::TooSmall);
+        }
+        // ファイルを読み込んだバッファがアラインされているとは限らないので、コピーして読む
+        let header = unsafe { (data.as_ptr() as *const ElfEhdr).read_unaligned() };
+
+        let ident = &header.e_ident;
+        if ident[..ELF_MAGIC.len()] != ELF_MAGIC {
+            return Err(ElfError::InvalidMagic);
+        }
+        if ident[EI_CLASS] != ELF_CLASS_64 {
+            return Err(ElfError::UnsupportedClass(ident[EI_CLASS]));
+        }
+        if ident[EI_DATA] != ELF_DATA_2LSB {
+            return Err(ElfError::UnsupportedEndian(ident[EI_DATA]));
+        }
+        if ident[EI_VERSION] != EV_CURRENT {
+            return Err(ElfError::UnsupportedVersion(ident[EI_VERSION]));
+        }
+        if header.e_type != ET_EXEC && header.e_type != ET_DYN {
+            return Err(ElfError::UnsupportedType(header.e_type));
+        }
+        if header.e_machine != EM_X86_64 {
+            return Err(ElfError::UnsupportedMachine(header.e_machine));
+        }
+        if header.e_phentsize as usize != size_of::<ElfPhdr>() {
+            return Err(ElfError::InvalidProgramHeaderSize(header.e_phentsize));
+        }
+        let phdrs_end = (header.e_phnum as u64)
+            .checked_mul(size_of::<ElfPhdr>() as u64)
+            .and_then(|size| size.checked_add(header.e_phoff))
+            .ok_or(ElfError::ProgramHeaderOutOfBounds)?;
+        if phdrs_end > data.len() as u64 {
+            return Err(ElfError::ProgramHeaderOutOfBounds);
+        }
+
+        let image = ElfImage { data, header };
+        let mut has_load_segment = false;
+        for phdr in image.load_segments() {
+            has_load_segment = true;
+            if phdr.p_filesz > phdr.p_memsz {
+                return Err(ElfError::InvalidSegmentSize);
+            }
+            let file_end = phdr
+                .p_offset
+                .checked_add(phdr.p_filesz)
+                .ok_or(ElfError::SegmentOutOfBounds)?;
+            if file_end > data.len() as u64 {
+                return Err(ElfError::SegmentOutOfBounds);
+            }
+            phdr.p_vaddr
+                .checked_add(phdr.p_memsz)
+                .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+                .ok_or(ElfError::AddressOverflow)?;
+        }
+        if !has_load_segment {
+            return Err(ElfError::NoLoadableSegment);
+        }
+
+        let (start, end) = image.load_address_range();
+        if image.header.e_entry < start || image.header.e_entry >= end {
+            return Err(ElfError::EntryOutOfRange);
+        }
+        Ok(image)
+    }
+
+    pub fn header(&self) -> &ElfEhdr {
+        &self.header
+    }
+
+    // ET_DYNなら、任意のアドレスに展開して再配置できる
+    pub fn is_relocatable(&self) -> bool {
+        self.header.e_type == ET_DYN
+    }
+
+    // プログラムヘッダを一つずつ返す
+    pub fn program_headers(&self) -> impl Iterator<Item = ElfPhdr> + '_ {
+        let phoff = self.header.e_phoff as usize;
+        (0..self.header.e_phnum as usize).map(move |i| unsafe {
+            (self.data.as_ptr().add(phoff + i * size_of::<ElfPhdr>()) as *const ElfPhdr)
+                .read_unaligned()
+        })
+    }
+
+    // PT_LOADのプログラムヘッダだけを返す
+    pub fn load_segments(&self) -> impl Iterator<Item = ElfPhdr> + '_ {
+        self.program_headers()
+            .filter(|phdr| phdr.phdr_type() == Some(ElfPhdrType::PtLoad))
+    }
+
+    // すべてのPT_LOADセグメントを含む、ページ境界に揃えた範囲 [min vaddr, max vaddr)
+    pub fn load_address_range(&self) -> (u64, u64) {
+        let mut start = u64::MAX;
+        let mut end = 0;
+        for phdr in self.load_segments() {
+            start = start.min(phdr.p_vaddr);
+            end = end.max(phdr.p_vaddr + phdr.p_memsz);
+        }
+        (
+            start & !(PAGE_SIZE - 1),
+            (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+        )
+    }
+
+    // 展開に必要なバイト数(ページ単位)
+    pub fn load_size(&self) -> u64 {
+        let (start, end) = self.load_address_range();
+        end - start
+    }
+
+    // 展開先のアドレスが満たすべきアラインメント
+    pub fn load_align(&self) -> u64 {
+        self.load_segments()
+            .map(|phdr| phdr.p_align)
+            .filter(|align| align.is_power_of_two())
+            .fold(PAGE_SIZE, u64::max)
+    }
+
+    // リンク時のアドレスにそのまま展開する
+    pub fn load(
+        &self,
+        boot_services: &EfiBootServicesTable,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<LoadedImage> {
+        let (start, _) = self.load_address_range();
+        self.load_at(boot_services, start, progress)
+    }
+
+    // 最小の仮想アドレスがbaseになるように、各セグメントを物理アドレスに展開する
+    // 範囲全体を一度に確保し、ファイル上の内容をコピーしたあと、残り(BSS)を0で埋める
+    // ET_DYNの場合は、R_X86_64_RELATIVEの再配置も行う
+    // セグメントを一つ展開するごとに、(展開したセグメント数, 全体のセグメント数)でprogressを呼ぶ
+    pub fn load_at(
+        &self,
+        boot_services: &EfiBootServicesTable,
+        base: u64,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<LoadedImage> {
+        let (start, end) = self.load_address_range();
+        if base & (PAGE_SIZE - 1) != 0 || (!self.is_relocatable() && base != start) {
+            return Err(ElfError::EfiError(EfiStatus::InvalidParameter));
+        }
+        let slide = base.wrapping_sub(start);
+        let load_end = base
+            .checked_add(end - start)
+            .ok_or(ElfError::AddressOverflow)?;
+        let mut addr = base;
+        let pages = ((end - start) / PAGE_SIZE) as usize;
+        let status = boot_services.allocate_pages(pages, &mut addr);
+        if status != EfiStatus::Success {
+            return Err(status.into());
+        }
+
+        let num_segments = self.load_segments().count();
+        for (i, phdr) in self.load_segments().enumerate() {
+            let dst = phdr.p_vaddr.wrapping_add(slide) as *mut u8;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.data.as_ptr().add(phdr.p_offset as usize),
+                    dst,
+                    phdr.p_filesz as usize,
+                );
+                core::ptr::write_bytes(
+                    dst.add(phdr.p_filesz as usize),
+                    0,
+                    (phdr.p_memsz - phdr.p_filesz) as usize,
+                );
+            }
+            progress(i + 1, num_segments);
+        }
+
+        let loaded = LoadedImage {
+            start: base,
+            end: load_end,
+            entry: self.header.e_entry.wrapping_add(slide),
+            slide,
+        };
+        if self.is_relocatable()
+            && let Err(e) = self.apply_relocations(&loaded)
+        {
+            let _ = boot_services.free_pages(base, pages);
+            return Err(e);
+        }
+        Ok(loaded)
+    }
+
+    // PT_DYNAMICからDT_RELA/DT_RELASZ/DT_RELAENTを探す
+    // 見つかった場合は、(RELAテーブルの仮想アドレス, バイト数)を返す
+    fn find_rela_table(&self) -> Result<Option<(u64, u64)>> {
+        let dynamic = match self
+            .program_headers()
+            .find(|phdr| phdr.phdr_type() == Some(ElfPhdrType::PtDynamic))
+        {
+            Some(phdr) => phdr,
+            None => return Ok(None),
+        };
+        let dynamic_end = dynamic
+            .p_offset
+            .checked_add(dynamic.p_filesz)
+            .ok_or(ElfError::InvalidDynamicSection)?;
+        if dynamic_end > self.data.len() as u64 {
+            return Err(ElfError::InvalidDynamicSection);
+        }
+
+        let mut rela = None;
+        let mut rela_size = None;
+        let mut rela_entry_size = size_of::<ElfRela>() as u64;
+        let num_entries = dynamic.p_filesz as usize / size_of::<ElfDyn>();
+        for i in 0..num_entries {
+            let entry = unsafe {
+                (self
+                    .data
+                    .as_ptr()
+                    .add(dynamic.p_offset as usize + i * size_of::<ElfDyn>())
+                    as *const ElfDyn)
+                    .read_unaligned()
+            };
+            match entry.d_tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(entry.d_val),
+                DT_RELASZ => rela_size = Some(entry.d_val),
+                DT_RELAENT => rela_entry_size = entry.d_val,
+                _ => {}
+            }
+        }
+        if rela_entry_size != size_of::<ElfRela>() as u64 {
+            return Err(ElfError::InvalidDynamicSection);
+        }
+        match (rela, rela_size) {
+            (Some(rela), Some(rela_size)) => Ok(Some((rela, rela_size))),
+            (None, None) => Ok(None),
+            _ => Err(ElfError::InvalidDynamicSection),
+        }
+    }
+
+    // 展開済みのイメージに、R_X86_64_RELATIVEの再配置を適用する
+    fn apply_relocations(&self, loaded: &LoadedImage) -> Result<()> {
+        let (rela, rela_size) = match self.find_rela_table()? {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+        // 展開した範囲に収まっているかを確認するための関数
+        // リンク時のアドレスより下に展開した場合、slideは2の補数になっているので、wrapping_addで足す
+        let in_image = |vaddr: u64, size: u64| -> Option<u64> {
+            let addr = vaddr.wrapping_add(loaded.slide);
+            let end = addr.checked_add(size)?;
+            if addr >= loaded.start && end <= loaded.end {
+                Some(addr)
+            } else {
+                None
+            }
+        };
+        let table = in_image(rela, rela_size).ok_or(ElfError::InvalidDynamicSection)?;
+
+        let num_relocations = rela_size as usize / size_of::<ElfRela>();
+        for i in 0..num_relocations {
+            let entry = unsafe {
+                ((table as usize + i * size_of::<ElfRela>()) as *const ElfRela).read_unaligned()
+            };
+            match entry.r_type() {
+                R_X86_64_NONE => {}
+                R_X86_64_RELATIVE => {
+                    let target = in_image(entry.r_offset, size_of::<u64>() as u64)
+                        .ok_or(ElfError::RelocationOutOfRange)?;
+                    let value = loaded.slide.wrapping_add(entry.r_addend as u64);
+                    unsafe { (target as *mut u64).write_unaligned(value) };
+                }
+                r_type => return Err(ElfError::UnsupportedRelocation(r_type)),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+// 8x16ドットのASCIIフォント。build.rsがtools/hankaku.txtから生成する
+pub const FONT_WIDTH: usize = 8;
+pub const FONT_HEIGHT: usize = 16;
+
+static FONT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/hankaku.bin"));
+
+// 文字cの各行のビット列。最上位ビットが左端のドット
+pub fn glyph(c: u8) -> Option<&'static [u8]> {
+    let start = c as usize * FONT_HEIGHT;
+    FONT.get(start..start + FONT_HEIGHT)
+}
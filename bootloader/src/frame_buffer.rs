@@ -0,0 +1,269 @@
+// GOPのフレームバッファへの描画
+// フレームバッファに直接書き込めるモードでは、FrameBufferConfigの情報だけで描くので、
+// ブートサービスの終了後も使える。PixelBltOnlyのモードでは、ブートサービスが使える間だけBltで描く。
+use core::fmt;
+
+use crate::boot_info::{FrameBufferConfig, PixelBitmask, PixelFormat};
+use crate::font::{FONT_HEIGHT, FONT_WIDTH, glyph};
+use crate::uefi::graphics::{
+    EfiGraphicsOutputBltOperation, EfiGraphicsOutputBltPixel, EfiGraphicsOutputProtocol,
+    EfiGraphicsPixelFormat,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(0xff, 0xff, 0xff);
+    pub const GRAY: Color = Color::new(0x80, 0x80, 0x80);
+    // エラーを表示するパネルの背景
+    pub const DARK_RED: Color = Color::new(0x80, 0, 0);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Color {
+        Color { red, green, blue }
+    }
+}
+
+impl From<Color> for EfiGraphicsOutputBltPixel {
+    fn from(color: Color) -> Self {
+        EfiGraphicsOutputBltPixel {
+            blue: color.blue,
+            green: color.green,
+            red: color.red,
+            reserved: 0,
+        }
+    }
+}
+
+// 8ビットの値を、maskのビット数に合わせて拡大・縮小してから、maskの位置に置く
+fn place_in_mask(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let bits = mask.count_ones();
+    let value = if bits >= 8 {
+        (value as u32) << (bits - 8)
+    } else {
+        (value as u32) >> (8 - bits)
+    };
+    (value << shift) & mask
+}
+
+// PixelBitMaskのモードでの、colorのピクセル値
+pub fn bitmask_pixel(bitmask: &PixelBitmask, color: Color) -> u32 {
+    place_in_mask(color.red, bitmask.red_mask)
+        | place_in_mask(color.green, bitmask.green_mask)
+        | place_in_mask(color.blue, bitmask.blue_mask)
+}
+
+enum Target<'a> {
+    // フレームバッファに直接書き込む
+    Memory(FrameBufferConfig),
+    // PixelBltOnlyのモードなので、Bltで描く
+    Blt(&'a EfiGraphicsOutputProtocol<'a>),
+}
+
+pub struct FrameBuffer<'a> {
+    target: Target<'a>,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> FrameBuffer<'a> {
+    // フレームバッファに直接書き込めるフォーマット(RGB, BGR, PixelBitMask)でなければNone
+    pub fn new(config: FrameBufferConfig) -> Option<FrameBuffer<'a>> {
+        match config.pixel_format {
+            PixelFormat::kPixelRGBResv8BitPerColor
+            | PixelFormat::kPixelBGRResv8BitPerColor
+            | PixelFormat::kPixelBitMask => Some(FrameBuffer {
+                width: config.horizontal_resolution as usize,
+                height: config.vertical_resolution as usize,
+                target: Target::Memory(config),
+            }),
+            PixelFormat::kPixelBltOnly => None,
+        }
+    }
+
+    // GOPの現在のモードで描く。PixelBltOnlyのときはBltを使うので、ブートサービスの終了後は使えない
+    pub fn from_gop(gop: &'a EfiGraphicsOutputProtocol<'a>) -> Option<FrameBuffer<'a>> {
+        if gop.mode.info.pixel_format != EfiGraphicsPixelFormat::PixelBltOnly {
+            return FrameBuffer::new(FrameBufferConfig::from(gop.mode));
+        }
+        Some(FrameBuffer {
+            width: gop.mode.info.horizontal_resolution as usize,
+            height: gop.mode.info.vertical_resolution as usize,
+            target: Target::Blt(gop),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // 画面の外は無視する
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.fill_rect(x, y, 1, 1, color);
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        if width == 0 || height == 0 {
+            return;
+        }
+        match &self.target {
+            Target::Memory(config) => {
+                for dy in 0..height {
+                    for dx in 0..width {
+                        write_memory(config, x + dx, y + dy, color);
+                    }
+                }
+            }
+            Target::Blt(gop) => {
+                let mut pixel = EfiGraphicsOutputBltPixel::from(color);
+                let _ = gop.blt(
+                    &mut pixel,
+                    EfiGraphicsOutputBltOperation::BltVideoFill,
+                    0,
+                    0,
+                    x,
+                    y,
+                    width,
+                    height,
+                    0,
+                );
+            }
+        }
+    }
+
+    // 表示できない文字は'?'にする
+    pub fn draw_char(&mut self, x: usize, y: usize, c: u8, fg: Color, bg: Color) {
+        let Some(rows) = glyph(c).or_else(|| glyph(b'?')) else {
+            return;
+        };
+        let color_at = |dx: usize, dy: usize| {
+            if (rows[dy] << dx) & 0x80 != 0 { fg } else { bg }
+        };
+        match &self.target {
+            Target::Memory(_) => {
+                for dy in 0..FONT_HEIGHT {
+                    for dx in 0..FONT_WIDTH {
+                        self.write_pixel(x + dx, y + dy, color_at(dx, dy));
+                    }
+                }
+            }
+            // 一文字分をまとめて転送する
+            Target::Blt(gop) => {
+                if x + FONT_WIDTH > self.width || y + FONT_HEIGHT > self.height {
+                    return;
+                }
+                let mut pixels = [EfiGraphicsOutputBltPixel::default(); FONT_WIDTH * FONT_HEIGHT];
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = color_at(i % FONT_WIDTH, i / FONT_WIDTH).into();
+                }
+                let _ = gop.blt(
+                    pixels.as_mut_ptr(),
+                    EfiGraphicsOutputBltOperation::BltBufferToVideo,
+                    0,
+                    0,
+                    x,
+                    y,
+                    FONT_WIDTH,
+                    FONT_HEIGHT,
+                    0,
+                );
+            }
+        }
+    }
+
+    pub fn draw_str(&mut self, x: usize, y: usize, s: &str, fg: Color, bg: Color) {
+        for (i, c) in s.bytes().enumerate() {
+            self.draw_char(x + i * FONT_WIDTH, y, c, fg, bg);
+        }
+    }
+}
+
+fn write_memory(config: &FrameBufferConfig, x: usize, y: usize, color: Color) {
+    let value = match config.pixel_format {
+        PixelFormat::kPixelRGBResv8BitPerColor => {
+            u32::from_le_bytes([color.red, color.green, color.blue, 0])
+        }
+        PixelFormat::kPixelBitMask => bitmask_pixel(&config.pixel_bitmask, color),
+        _ => u32::from_le_bytes([color.blue, color.green, color.red, 0]),
+    };
+    let index = y * config.pixels_per_scan_line as usize + x;
+    unsafe {
+        (config.frame_buffer_base as *mut u32)
+            .add(index)
+            .write_volatile(value);
+    }
+}
+
+// 矩形の中に文字列を書いていく。右端で折り返し、下端を超えた分は捨てる
+pub struct TextWriter<'a, 'b> {
+    frame_buffer: &'a mut FrameBuffer<'b>,
+    left: usize,
+    right: usize,
+    bottom: usize,
+    x: usize,
+    y: usize,
+    fg: Color,
+    bg: Color,
+}
+
+impl<'a, 'b> TextWriter<'a, 'b> {
+    pub fn new(
+        frame_buffer: &'a mut FrameBuffer<'b>,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        fg: Color,
+        bg: Color,
+    ) -> TextWriter<'a, 'b> {
+        TextWriter {
+            frame_buffer,
+            left: x,
+            right: x + width,
+            bottom: y + height,
+            x,
+            y,
+            fg,
+            bg,
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.x = self.left;
+        self.y += FONT_HEIGHT;
+    }
+}
+
+impl fmt::Write for TextWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            if c == b'\n' {
+                self.new_line();
+                continue;
+            }
+            if self.x + FONT_WIDTH > self.right {
+                self.new_line();
+            }
+            if self.y + FONT_HEIGHT > self.bottom {
+                return Ok(());
+            }
+            self.frame_buffer
+                .draw_char(self.x, self.y, c, self.fg, self.bg);
+            self.x += FONT_WIDTH;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+// カーネルのアドレス空間配置のランダム化(KASLR)
+// 位置独立なカーネルを、メモリマップの空き領域の中からランダムに選んだアドレスに展開する
+use crate::memory_map_holder::MemoryMapHolder;
+
+// 展開先のアドレスは2MiBに揃える。ページングで大きなページを使えるようにするため
+pub const KASLR_ALIGN: u64 = 0x200000;
+
+// 空き領域の候補の中から、randomで決まる一つを選ぶ
+pub fn pick_load_base(
+    memory_map: &MemoryMapHolder,
+    size: u64,
+    align: u64,
+    min_addr: u64,
+    random: u64,
+) -> Option<u64> {
+    let align = align.max(KASLR_ALIGN);
+    let total: u64 = memory_map
+        .free_range_candidates(size, align, min_addr)
+        .map(|(_, count)| count)
+        .sum();
+    if total == 0 {
+        return None;
+    }
+    let mut index = random % total;
+    for (start, count) in memory_map.free_range_candidates(size, align, min_addr) {
+        if index < count {
+            return Some(start + index * align);
+        }
+        index -= count;
+    }
+    None
+}
@@ -1,7 +1,23 @@
 #![no_std]
 #![no_main]
 
+pub mod bmp;
+pub mod boot_info;
+pub mod chainload;
+pub mod cmdline;
+pub mod config;
 pub mod elf;
+pub mod font;
+pub mod frame_buffer;
+pub mod kaslr;
+pub mod log;
+pub mod memmap_export;
 pub mod memory_map_holder;
+pub mod menu;
+pub mod panic;
+pub mod random;
+pub mod serial;
+pub mod splash;
 pub mod stack;
 pub mod uefi;
+pub mod volume;
@@ -0,0 +1,289 @@
+// ブートローダのログ
+// error!, warn!, info!, debug!で出力し、画面(con_out), シリアルポート(COM1), \boot.logに書き出す。
+// 画面には設定ファイルのlogで指定したレベルまでを表示し、それ以外の出力先にはすべてのレベルを書き出す。
+// 書き出した内容はリングバッファにも残しておき、カーネルに渡す。
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr::null_mut;
+
+use crate::boot_info::MemoryRange;
+use crate::serial::{COM1_PORT, SerialPort};
+use crate::uefi::file::File;
+use crate::uefi::memory::EfiMemoryType;
+use crate::uefi::text::EfiSimpleTextOutputProtocol;
+use crate::uefi::types::{EfiVoid, Result};
+use crate::uefi::{EfiBootServicesTable, EfiSystemTable};
+
+pub use crate::config::LogLevel;
+
+pub const LOG_FILE_PATH: &str = "\\boot.log";
+// カーネルに渡すログのバイト数の上限。超えた分は古いものから捨てる
+pub const LOG_BUFFER_SIZE: usize = 16 * 1024;
+// 一つのログをまとめて書き出すためのバッファ。長いログは、このサイズごとに書き出す
+const RECORD_BUFFER_SIZE: usize = 256;
+// 画面に一度に書き出すUCS-2の文字数
+const SCREEN_CHUNK_SIZE: usize = 128;
+
+impl LogLevel {
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Error => "[ERROR] ",
+            LogLevel::Warn => "[WARN] ",
+            LogLevel::Info => "[INFO] ",
+            LogLevel::Debug => "[DEBUG] ",
+        }
+    }
+}
+
+struct Logger {
+    // 画面に表示するレベル
+    level: LogLevel,
+    // falseの間は、画面に何も表示しない。スプラッシュ画面を表示している間に使う
+    screen_enabled: bool,
+    con_out: Option<&'static EfiSimpleTextOutputProtocol>,
+    serial: Option<SerialPort>,
+    file: Option<File>,
+    ring: [u8; LOG_BUFFER_SIZE],
+    // これまでにringに書き込んだバイト数。LOG_BUFFER_SIZEを超えたら、古いものから上書きしている
+    written: usize,
+}
+
+impl Logger {
+    fn emit(&mut self, bytes: &[u8], to_screen: bool) {
+        if to_screen && let Some(con_out) = self.con_out {
+            write_screen(con_out, bytes);
+        }
+        if let Some(serial) = self.serial.as_mut() {
+            for &byte in bytes {
+                if byte == b'\n' {
+                    serial.write_byte(b'\r');
+                }
+                serial.write_byte(byte);
+            }
+        }
+        // 書き込めなくなったファイルには、それ以降書き込まない
+        if let Some(file) = self.file.as_mut()
+            && file.write_all(bytes).is_err()
+        {
+            self.file = None;
+        }
+        for &byte in bytes {
+            self.ring[self.written % LOG_BUFFER_SIZE] = byte;
+            self.written += 1;
+        }
+    }
+
+    // リングバッファの内容を、古い順に二つに分けて返す
+    // 上書きされている場合は、途中から始まる最初の行を除く
+    fn contents(&self) -> (&[u8], &[u8]) {
+        if self.written <= LOG_BUFFER_SIZE {
+            return (&self.ring[..self.written], &[]);
+        }
+        let start = self.written % LOG_BUFFER_SIZE;
+        let (newer, older) = self.ring.split_at(start);
+        match older.iter().position(|&byte| byte == b'\n') {
+            Some(i) => (&older[i + 1..], newer),
+            None => (&[], newer),
+        }
+    }
+}
+
+// UTF-8のログをUCS-2に変換し、SCREEN_CHUNK_SIZEごとにまとめてcon_outに書き出す
+// UCS-2で表せないBMPの外の文字と、UTF-8として不正なバイト列は'?'にする
+// パニックの処理からも呼ばれるので、書き出しに失敗しても何もしない
+fn write_screen(con_out: &EfiSimpleTextOutputProtocol, bytes: &[u8]) {
+    let mut buffer = [0u16; SCREEN_CHUNK_SIZE + 1];
+    let mut len = 0;
+    let chars = bytes.utf8_chunks().flat_map(|chunk| {
+        chunk
+            .valid()
+            .chars()
+            .chain((!chunk.invalid().is_empty()).then_some('?'))
+    });
+    for c in chars {
+        // '\n'の前には'\r'を入れるので、2文字分の空きを確認する
+        if len + 2 > SCREEN_CHUNK_SIZE {
+            buffer[len] = 0;
+            let _ = con_out.output_string(&buffer[..=len]);
+            len = 0;
+        }
+        if c == '\n' {
+            buffer[len] = b'\r' as u16;
+            len += 1;
+        }
+        buffer[len] = u16::try_from(u32::from(c)).unwrap_or(b'?' as u16);
+        len += 1;
+    }
+    if len > 0 {
+        buffer[len] = 0;
+        let _ = con_out.output_string(&buffer[..=len]);
+    }
+}
+
+// UEFIのブートローダはシングルスレッドで動き、割り込みの中からログを出力することもないので、
+// 排他制御をせずに一つのLoggerを使う
+struct GlobalLogger(UnsafeCell<Logger>);
+
+unsafe impl Sync for GlobalLogger {}
+
+static LOGGER: GlobalLogger = GlobalLogger(UnsafeCell::new(Logger {
+    level: LogLevel::Warn,
+    screen_enabled: true,
+    con_out: None,
+    serial: None,
+    file: None,
+    ring: [0; LOG_BUFFER_SIZE],
+    written: 0,
+}));
+
+fn logger() -> &'static mut Logger {
+    unsafe { &mut *LOGGER.0.get() }
+}
+
+// 画面とシリアルポートへの出力を始める。initより前のログは、リングバッファにだけ残る
+pub fn init(system_table: &EfiSystemTable) {
+    let logger = logger();
+    logger.con_out = Some(system_table.con_out());
+    logger.serial = SerialPort::init(COM1_PORT);
+}
+
+pub fn set_level(level: LogLevel) {
+    logger().level = level;
+}
+
+pub fn level() -> LogLevel {
+    logger().level
+}
+
+pub fn set_screen_enabled(enabled: bool) {
+    logger().screen_enabled = enabled;
+}
+
+// rootに\boot.logを作り、それまでのログを書き出してから、以降のログも書き込む
+pub fn open_file(root: &File) -> Result<()> {
+    let mut file = root.create(LOG_FILE_PATH)?;
+    let logger = logger();
+    let (older, newer) = logger.contents();
+    file.write_all(older)?;
+    file.write_all(newer)?;
+    logger.file = Some(file);
+    Ok(())
+}
+
+// \boot.logを閉じる。ファイルの操作はメモリマップを変えることがあるので、
+// 最終的なメモリマップを取得する前に呼ぶこと
+pub fn close_file() {
+    if let Some(mut file) = logger().file.take() {
+        let _ = file.flush();
+    }
+}
+
+// ブートサービスを終了した後は、シリアルポートとリングバッファにだけ書き込む
+pub fn exit_boot_services() {
+    let logger = logger();
+    logger.con_out = None;
+    // 閉じ忘れたファイルは、ブートサービスなしでは閉じられないので、そのまま捨てる
+    if let Some(file) = logger.file.take() {
+        core::mem::forget(file);
+    }
+}
+
+// これまでのログをLOADER_DATAのプールにコピーして、カーネルに渡す範囲を返す
+pub fn copy_to_pool(boot_services: &EfiBootServicesTable) -> Result<MemoryRange> {
+    let (older, newer) = logger().contents();
+    let size = older.len() + newer.len();
+    if size == 0 {
+        return Ok(MemoryRange::default());
+    }
+    let mut buffer = null_mut::<EfiVoid>();
+    boot_services
+        .allocate_pool(EfiMemoryType::LOADER_DATA, size, &mut buffer)
+        .into_result()?;
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer, size) };
+    buffer[..older.len()].copy_from_slice(older);
+    buffer[older.len()..].copy_from_slice(newer);
+    Ok(MemoryRange {
+        base: buffer.as_ptr() as u64,
+        size: size as u64,
+    })
+}
+
+// 一つのログを書式化しながら、RECORD_BUFFER_SIZEごとに書き出す
+struct RecordWriter<'a> {
+    logger: &'a mut Logger,
+    to_screen: bool,
+    buffer: [u8; RECORD_BUFFER_SIZE],
+    len: usize,
+}
+
+impl RecordWriter<'_> {
+    fn flush(&mut self) {
+        self.logger.emit(&self.buffer[..self.len], self.to_screen);
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for RecordWriter<'_> {
+    // 画面に出すときにUCS-2に変換するので、文字の途中では区切らない
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len + c.len_utf8() > self.buffer.len() {
+                self.flush();
+            }
+            c.encode_utf8(&mut self.buffer[self.len..]);
+            self.len += c.len_utf8();
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    let logger = logger();
+    let to_screen = logger.screen_enabled && level <= logger.level;
+    let mut writer = RecordWriter {
+        logger,
+        to_screen,
+        buffer: [0; RECORD_BUFFER_SIZE],
+        len: 0,
+    };
+    let _ = fmt::Write::write_str(&mut writer, level.label());
+    let _ = fmt::Write::write_fmt(&mut writer, args);
+    let _ = fmt::Write::write_str(&mut writer, "\n");
+    writer.flush();
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::_log($level, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::LogLevel::Error, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::LogLevel::Warn, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::LogLevel::Info, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::log::LogLevel::Debug, $($arg)*)
+    };
+}
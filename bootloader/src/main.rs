@@ -1,195 +1,221 @@
 #![no_std]
 #![no_main]
 
-use core::arch::asm;
-
-use core::fmt::Write;
-use core::fmt::write;
 use core::mem::size_of;
 use core::panic::PanicInfo;
 use core::ptr::null_mut;
-use core::slice;
-use core::writeln;
 // pub mod memory_map_holder;
 // pub mod uefi;
 // mod uefi_alloc;
 
-use bootloader::elf::ElfEhdr;
-use bootloader::elf::ElfPhdr;
-use bootloader::elf::ElfPhdrType;
+use bootloader::bmp::Bmp;
+use bootloader::boot_info::{
+    BootInfo, FrameBufferConfig, KernelEntryPoint, MemoryMapInfo, MemoryRange,
+};
+use bootloader::chainload::chainload;
+use bootloader::cmdline::{copy_cmdline, load_options_to_cmdline};
+use bootloader::config::{BootConfig, BootEntryKind, CONFIG_FILE_MAX_SIZE, CONFIG_PATH};
+use bootloader::elf::ElfImage;
+use bootloader::kaslr::pick_load_base;
+use bootloader::memmap_export::export_memory_map;
 use bootloader::memory_map_holder::MemoryMapHolder;
-use bootloader::stack::BufWriter;
-use bootloader::uefi::file::{EfiFileInfo, EfiFileProtocol, EfiSimpleFileSystemProtocol};
+use bootloader::menu::{CMDLINE_BUFFER_SIZE, show_boot_menu};
+use bootloader::random::random_u64;
+use bootloader::splash::{LOGO_FILE_MAX_SIZE, Splash};
+use bootloader::uefi::file::{EfiSimpleFileSystemProtocol, File};
 use bootloader::uefi::graphics::EfiGraphicsOutputProtocol;
-use bootloader::uefi::memory::EfiMemoryType;
+use bootloader::uefi::memory::{EfiMemoryType, UEFI_PAGE_SIZE};
 use bootloader::uefi::open_gop;
-use bootloader::uefi::text::EfiSimpleTextOutputProtocolWriter;
 use bootloader::uefi::types::{
-    EFI_FILE_INFO_GUID, EFI_FILE_MODE_CREATE, EFI_FILE_MODE_READ, EFI_FILE_MODE_WRITE,
-    EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID, EFI_LOADED_IMAGE_PROTOCOL_GUID,
-    EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID, EfiGuid, EfiHandle, EfiLocateSearchType, EfiStatus,
-    EfiVoid, Error,
+    EFI_ACPI_20_TABLE_GUID, EFI_ACPI_TABLE_GUID, EFI_FILE_MODE_READ, EfiHandle, EfiStatus, EfiVoid,
+    Error, Result, SMBIOS_TABLE_GUID, SMBIOS3_TABLE_GUID,
 };
 use bootloader::uefi::{EfiLoadedImageProtocol, EfiSystemTable};
+use bootloader::volume::{FoundFile, find_file};
+use bootloader::{debug, error, info, log, panic, warn};
 
-type EntryPointType = extern "C" fn(usize, u32, u32, u32, i32) -> usize;
-const MEMMAP_PATH: &[u16; 12] = &[
-    (b'\\' as u16),
-    (b'm' as u16),
-    (b'e' as u16),
-    (b'm' as u16),
-    (b'm' as u16),
-    (b'a' as u16),
-    (b'p' as u16),
-    (b'.' as u16),
-    (b't' as u16),
-    (b'x' as u16),
-    (b't' as u16),
-    (b'\0' as u16), // NULL終端
-];
-
-const KERNEL_PATH: &[u16; 12] = &[
-    (b'\\' as u16),
-    (b'k' as u16),
-    (b'e' as u16),
-    (b'r' as u16),
-    (b'n' as u16),
-    (b'e' as u16),
-    (b'l' as u16),
-    (b'.' as u16),
-    (b'e' as u16),
-    (b'l' as u16),
-    (b'f' as u16),
-    (b'\0' as u16),
-];
-
-fn open_root_dir(
-    image_handle: EfiHandle,
-    efi_system_table: &EfiSystemTable,
-    root: *mut *mut EfiFileProtocol,
-) -> EfiStatus {
-    let mut output_writer = EfiSimpleTextOutputProtocolWriter::new(efi_system_table.con_out());
-    // ファイルを開いてみる
-    let mut loaded_image: *mut EfiLoadedImageProtocol = null_mut::<EfiLoadedImageProtocol>();
-    let mut fs: *mut EfiSimpleFileSystemProtocol = null_mut::<EfiSimpleFileSystemProtocol>();
-
-    let status = efi_system_table.boot_services.open_protocol(
-        image_handle,
-        &EFI_LOADED_IMAGE_PROTOCOL_GUID,
-        &mut loaded_image as *mut *mut EfiLoadedImageProtocol as *mut *mut EfiVoid,
-        image_handle,
-        null_mut::<u64>() as u64,
-    );
-
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open loaded image protocol");
-        return status;
-    }
+// 位置独立なカーネルを展開するときの最小の物理アドレス
+// 1MiB未満はレガシーな用途に使われていることが多いので避ける
+const KERNEL_MIN_LOAD_ADDRESS: u64 = 0x100000;
 
-    let loaded_image = unsafe { &*loaded_image };
-    let status = efi_system_table.boot_services.open_protocol(
-        loaded_image.device_handle,
-        &EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID,
-        &mut fs as *mut *mut EfiSimpleFileSystemProtocol as *mut *mut EfiVoid,
-        image_handle,
-        null_mut::<u64>() as u64,
-    );
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open simple file system protocol");
-        return status;
-    }
-    output_writer.write_str("successfully opened simple file system protocol status: ");
-    output_writer.write_str(status.to_string());
-    output_writer.write_str("\n");
+fn open_root_dir(image_handle: EfiHandle, efi_system_table: &EfiSystemTable) -> Result<File> {
+    // ファイルを開いてみる
+    let boot_services = efi_system_table.boot_services;
+    let loaded_image = boot_services
+        .open_protocol::<EfiLoadedImageProtocol>(image_handle, image_handle)
+        .inspect_err(|e| error!("Failed to open loaded image protocol: {e:?}"))?;
 
-    // fsをEfiSimpleFileSystemProtocolにキャスト
-    let fs: &EfiSimpleFileSystemProtocol = unsafe { &*fs };
+    let fs = boot_services
+        .open_protocol::<EfiSimpleFileSystemProtocol>(loaded_image.device_handle, image_handle)
+        .inspect_err(|e| error!("Failed to open simple file system protocol: {e:?}"))?;
+    debug!("successfully opened simple file system protocol");
 
     // ルートディレクトリを開く
-    output_writer.write_str("Opening root directory...\n");
-    let status = fs.open_volume(root);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open root directory");
-        return status;
+    let root =
+        File::open_volume(fs).inspect_err(|e| error!("Failed to open root directory: {e:?}"))?;
+    debug!("Successfully opened root directory");
+    Ok(root)
+}
+
+// 設定ファイルを読み込む。ファイルがない場合や読めない場合は、デフォルトの設定を使う
+// 読み込んだ内容は、カーネルに渡すまで参照するので、LOADER_DATAのプールに置いたままにする
+fn load_boot_config(efi_system_table: &EfiSystemTable, root: &File) -> BootConfig<'static> {
+    let mut config_file = match root.open(CONFIG_PATH, EFI_FILE_MODE_READ) {
+        Ok(file) => file,
+        Err(Error::EfiError(EfiStatus::NotFound)) => {
+            info!("boot.cfg not found, using default configuration");
+            return BootConfig::default();
+        }
+        Err(e) => {
+            warn!("Failed to open boot.cfg: {e:?}");
+            return BootConfig::default();
+        }
+    };
+    match config_file.size() {
+        Ok(size) if size <= CONFIG_FILE_MAX_SIZE as u64 => {}
+        Ok(size) => {
+            warn!("boot.cfg is too large ({size} bytes), using default configuration");
+            return BootConfig::default();
+        }
+        Err(e) => {
+            warn!("Failed to read boot.cfg: {e:?}");
+            return BootConfig::default();
+        }
     }
-    output_writer.write_str("Successfully opened root directory status: ");
-    output_writer.write_str(status.to_string());
-    output_writer.write_str("\n");
-    return status;
+    let text =
+        match config_file.read_to_end(efi_system_table.boot_services, EfiMemoryType::LOADER_DATA) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Failed to read boot.cfg: {e:?}");
+                return BootConfig::default();
+            }
+        };
+
+    let text = match core::str::from_utf8(text) {
+        Ok(text) => text,
+        Err(_) => {
+            warn!("boot.cfg is not valid UTF-8, using default configuration");
+            return BootConfig::default();
+        }
+    };
+    BootConfig::parse(text, |warning| warn!("{warning}"))
 }
 
-fn get_memmap_file(
+// カーネルを、すべてのファイルシステムのボリュームから探す
+// 見つからなければ、ブートボリュームにあるカーネルの候補を表示する
+fn get_kernel_file(
+    image_handle: EfiHandle,
     efi_system_table: &EfiSystemTable,
-    root: &EfiFileProtocol,
-    memmap_file: &mut *mut EfiFileProtocol,
-) -> EfiStatus {
-    let mut output_writer = EfiSimpleTextOutputProtocolWriter::new(efi_system_table.con_out());
-
-    let status = root.open(
-        memmap_file,
-        MEMMAP_PATH,
-        EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE | EFI_FILE_MODE_CREATE,
-        0,
-    );
-
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open memory map file\n");
-        return status;
+    root: &mut File,
+    kernel_path: &str,
+) -> Result<FoundFile> {
+    match find_file(image_handle, efi_system_table, kernel_path) {
+        Ok(found) => {
+            info!("Successfully opened {kernel_path}");
+            Ok(found)
+        }
+        Err(e) => {
+            error!("Failed to open {kernel_path}: {e:?}");
+            print_kernel_candidates(efi_system_table, root);
+            Err(e)
+        }
     }
-    output_writer.write_str("Successfully opened memory map file\n");
+}
 
-    return status;
+// initrdをLOADER_DATAのページに読み込み、その範囲を返す
+// ページ単位で確保するので、先頭はページ境界に揃い、カーネルは範囲をまとめて予約できる
+// カーネルはブートサービスの終了後もこの領域を読むので、解放しない
+fn load_initrd(efi_system_table: &EfiSystemTable, root: &File, path: &str) -> Result<MemoryRange> {
+    let boot_services = efi_system_table.boot_services;
+    let mut initrd_file = root.open(path, EFI_FILE_MODE_READ)?;
+    let size =
+        usize::try_from(initrd_file.size()?).map_err(|_| Error::Failed("initrd is too large"))?;
+    let pages = size.div_ceil(UEFI_PAGE_SIZE as usize).max(1);
+    let base = boot_services.allocate_any_pages(EfiMemoryType::LOADER_DATA, pages)?;
+    let data = unsafe { core::slice::from_raw_parts_mut(base as *mut u8, size) };
+    if let Err(e) = initrd_file.read_exact(data) {
+        let _ = boot_services.free_pages(base, pages);
+        return Err(e);
+    }
+    Ok(MemoryRange {
+        base,
+        size: size as u64,
+    })
 }
 
-fn get_kernel_file(
+// スプラッシュ画面を表示する。ロゴが読めなくても、進捗バーは表示する
+// ロゴのデータは描いた後に使わないので、解放する
+fn show_splash<'a>(
     efi_system_table: &EfiSystemTable,
-    root: &EfiFileProtocol,
-    kernel_file: &mut *mut EfiFileProtocol,
-) -> EfiStatus {
-    let mut output_writer = EfiSimpleTextOutputProtocolWriter::new(efi_system_table.con_out());
-
-    let status = root.open(
-        kernel_file,
-        KERNEL_PATH,
-        EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE | EFI_FILE_MODE_CREATE,
-        0,
-    );
+    root: &File,
+    gop: &'a EfiGraphicsOutputProtocol<'a>,
+    logo_path: &str,
+    title: &str,
+) -> Splash<'a> {
+    let boot_services = efi_system_table.boot_services;
+    let logo_data = match load_logo(efi_system_table, root, logo_path) {
+        Ok(data) => Some(data),
+        Err(Error::EfiError(EfiStatus::NotFound)) => {
+            info!("{logo_path} not found, showing splash without logo");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to load {logo_path}: {e:?}");
+            None
+        }
+    };
+    let logo = logo_data
+        .as_deref()
+        .and_then(|data| match Bmp::parse(data) {
+            Ok(logo) => Some(logo),
+            Err(e) => {
+                warn!("{logo_path} is not a supported BMP file: {e:?}");
+                None
+            }
+        });
+    let splash = Splash::new(gop, logo.as_ref(), title);
+    if let Some(data) = logo_data {
+        let _ = boot_services.free_pool(data.as_mut_ptr());
+    }
+    splash
+}
 
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open kernel.elf file\n");
-        return status;
+fn load_logo(
+    efi_system_table: &EfiSystemTable,
+    root: &File,
+    path: &str,
+) -> Result<&'static mut [u8]> {
+    let mut logo_file = root.open(path, EFI_FILE_MODE_READ)?;
+    if logo_file.size()? > LOGO_FILE_MAX_SIZE {
+        return Err(Error::Failed("logo file is too large"));
     }
-    output_writer.write_str("Successfully opened kernel.elf file\n");
-    return status;
+    logo_file.read_to_end(efi_system_table.boot_services, EfiMemoryType::LOADER_DATA)
 }
 
-fn write_to_memmap_file(memmap_file: &EfiFileProtocol, memory_map: &MemoryMapHolder) {
-    let mut temp_buffer = [0u8; 1024];
-    let mut buf_writer = BufWriter::new(&mut temp_buffer);
-
-    let mem_buffer = memory_map.memory_map_buffer.as_ptr() as usize;
-    let map_size = memory_map.memory_map_size;
-    let _ =
-        memmap_file.write_str("Index, Type, Type(name), PhysicalStart, NumberOfPages, Attribute\n");
-    let _ = writeln!(
-        buf_writer,
-        "map->buffer = {mem_buffer:0>8X}, map->map_size = {map_size:0>8X}"
-    );
-    let _ = memmap_file.write_str(buf_writer.as_str().expect(""));
-    buf_writer.flush();
-
-    for (i, mem_descriptor) in memory_map.iter().enumerate() {
-        let mem_type = mem_descriptor.memory_type as i64;
-        let mem_type_str = mem_descriptor.get_memory_type_str();
-        let physical_start = mem_descriptor.physical_start;
-        let num_of_pages = mem_descriptor.number_of_pages;
-        let attribute = mem_descriptor.attribute & 0xfffff;
-        let _ = writeln!(
-            buf_writer,
-            "{i}, {mem_type:X}, {mem_type_str}, {physical_start:0>8X}, {num_of_pages:X}, {attribute:X}"
-        );
-        let _ = memmap_file.write_str(buf_writer.as_str().expect(""));
-        buf_writer.flush();
+// UEFIシェルやブートオプションから渡された引数を、コマンドラインとして取得する
+fn get_load_options_cmdline(
+    image_handle: EfiHandle,
+    efi_system_table: &EfiSystemTable,
+) -> Result<Option<&'static str>> {
+    let boot_services = efi_system_table.boot_services;
+    let loaded_image =
+        boot_services.open_protocol::<EfiLoadedImageProtocol>(image_handle, image_handle)?;
+    load_options_to_cmdline(boot_services, loaded_image.load_options())
+}
+
+// カーネルが見つからなかったときのために、ルートディレクトリにある.elfファイルを表示する
+fn print_kernel_candidates(efi_system_table: &EfiSystemTable, root: &mut File) {
+    let Ok(entries) = root.read_dir(efi_system_table.boot_services) else {
+        return;
+    };
+    for info in entries.flatten() {
+        if !info.is_directory() && info.file_name().ends_with_ignore_ascii_case(".elf") {
+            warn!(
+                "  found \\{} ({} bytes)",
+                info.file_name(),
+                info.file_size()
+            );
+        }
     }
 }
 
@@ -199,30 +225,142 @@ pub extern "C" fn efi_main(
     image_handle: EfiHandle,
     efi_system_table: &EfiSystemTable,
 ) -> EfiStatus {
-    let mut console_buffer = [0u8; 1024];
-    let mut buf_writer = BufWriter::new(&mut console_buffer);
+    // ログを画面とシリアルポートに出力する
+    log::init(efi_system_table);
+    // パニックしたときに、メッセージを表示して再起動できるようにする
+    panic::init(image_handle, efi_system_table);
+
+    // ルートディレクトリを取得
+    let mut root = match open_root_dir(image_handle, efi_system_table) {
+        Ok(root) => root,
+        Err(e) => panic!("Failed to open root directory: {:?}", e),
+    };
+
+    // 設定ファイルを読み込む
+    let mut config = load_boot_config(efi_system_table, &root);
+    log::set_level(config.log_level);
 
-    // con_outで出力
-    let con_out = efi_system_table.con_out();
-    let mut output_writer = EfiSimpleTextOutputProtocolWriter::new(con_out);
+    // これまでのログも含めて、\boot.logに書き出す
+    if let Err(e) = log::open_file(&root) {
+        warn!("Failed to create {}: {e:?}", log::LOG_FILE_PATH);
+    }
+
+    // 起動時に引数が渡されていれば、設定ファイルのコマンドラインより優先する
+    match get_load_options_cmdline(image_handle, efi_system_table) {
+        Ok(Some(cmdline)) => config.override_cmdline(cmdline),
+        Ok(None) => {}
+        Err(e) => {
+            warn!("Failed to read load options: {e:?}");
+        }
+    }
+
+    // 起動する項目を選ぶ。timeoutが0のときは、メニューを表示せずに最初の項目を使う
+    // 他のEFIアプリケーションを起動した場合は、終了したらカウントダウンなしでメニューに戻る
+    let mut cmdline_buffer = [0u8; CMDLINE_BUFFER_SIZE];
+    let entries = config.entries();
+    let mut timeout = Some(config.timeout);
+    let (entry, cmdline) = loop {
+        let (entry, cmdline) = if timeout == Some(0) {
+            (&entries[0], entries[0].cmdline)
+        } else {
+            match show_boot_menu(efi_system_table, entries, timeout, &mut cmdline_buffer) {
+                Ok((index, cmdline)) => (&entries[index], cmdline),
+                // メニューが使えないときに他のEFIアプリケーションを選ぶと、戻ってきたときに
+                // またメニューが失敗して同じ項目を起動し続けるので、カーネルの項目を起動する
+                // BootConfigは、カーネルの項目を必ず一つは持っている
+                Err(e) => {
+                    warn!("Boot menu failed: {e:?}");
+                    let entry = entries
+                        .iter()
+                        .find(|entry| entry.kind == BootEntryKind::Kernel)
+                        .unwrap_or(&entries[0]);
+                    (entry, entry.cmdline)
+                }
+            }
+        };
+        if entry.kind == BootEntryKind::Kernel {
+            break (entry, cmdline);
+        }
+        info!("Chainloading {}", entry.path);
+        match chainload(image_handle, efi_system_table, &root, entry.path, cmdline) {
+            Ok(EfiStatus::Success) => info!("{} exited", entry.path),
+            Ok(status) => warn!(
+                "{} exited with {} (0x{:X})",
+                entry.path,
+                status.to_string(),
+                status.0
+            ),
+            Err(e) => error!("Failed to chainload {}: {e:?}", entry.path),
+        }
+        timeout = None;
+    };
+    info!("Booting {}", entry.title);
+    if !cmdline.is_empty() {
+        info!("cmdline: {cmdline}");
+    }
 
     let gop = open_gop(image_handle, efi_system_table).unwrap();
+
+    // 画面のモードを選んで切り替える
+    for (mode_number, info) in gop.modes(efi_system_table.boot_services) {
+        debug!(
+            "GOP mode {mode_number}: {}x{}, {}",
+            info.horizontal_resolution,
+            info.vertical_resolution,
+            info.get_ppixel_format()
+        );
+    }
+    match gop.select_mode(efi_system_table.boot_services, config.resolution) {
+        Some(mode_number) if mode_number != gop.mode.mode => {
+            if let Err(e) = gop.set_mode(mode_number) {
+                warn!("Failed to set GOP mode {mode_number}: {e:?}");
+            }
+        }
+        Some(_) => {}
+        None => warn!("No GOP mode with a linear frame buffer found, keeping current mode"),
+    }
+    if let Some((width, height)) = config.resolution
+        && (gop.mode.info.horizontal_resolution != width
+            || gop.mode.info.vertical_resolution != height)
+    {
+        warn!("Resolution {width}x{height} is not available");
+    }
+    if !gop.mode.info.is_direct_color() {
+        // 選んだモードに切り替えられなかった場合は、フレームバッファに直接書ける他のモードを順に試す
+        // カーネルはBltを使えないので、そういうモードがある限りPixelBltOnlyのモードでは起動しない
+        for (mode_number, _) in gop
+            .modes(efi_system_table.boot_services)
+            .filter(|(_, info)| info.is_direct_color())
+        {
+            if gop.set_mode(mode_number).is_ok() {
+                warn!("Falling back to GOP mode {mode_number}");
+                break;
+            }
+        }
+    }
+    if !gop.mode.info.is_direct_color() {
+        // ブートローダはBltで描けるが、カーネルはフレームバッファに直接書き込むので何も表示できない
+        error!("GOP mode has no linear frame buffer, the kernel will not be able to draw");
+    }
+    // ブートサービスが使える間のパニックは、このGOPで画面にも表示する
+    panic::set_gop(gop);
     let vram_addr: usize = gop.mode.frame_buffer_base;
     let vram_byte_size: usize = gop.mode.frame_buffer_size;
     let horizontal_resolution = gop.mode.info.horizontal_resolution;
     let vertical_resolution = gop.mode.info.vertical_resolution;
     let pixel_format = gop.mode.info.get_ppixel_format();
     let pixels_per_scan_line = gop.mode.info.pixels_per_scan_line;
-    let _ = writeln!(
-        buf_writer,
+    info!(
         "Resolution: {horizontal_resolution}x{vertical_resolution}, PixelFormat: {pixel_format}, {pixels_per_scan_line} p/l "
     );
-    let _ = writeln!(
-        buf_writer,
-        "frame_buffer_base: 0x{vram_addr:0>8X}, byte size: {vram_byte_size:X}"
-    );
-    output_writer.write_str(buf_writer.as_str().unwrap());
-    buf_writer.flush();
+    info!("frame_buffer_base: 0x{vram_addr:0>8X}, byte size: {vram_byte_size:X}");
+
+    // カーネルを読み込んでいる間は、スプラッシュ画面に進捗を表示する
+    let mut splash = if config.splash {
+        show_splash(efi_system_table, &root, gop, config.logo_path, entry.title)
+    } else {
+        Splash::disabled()
+    };
 
     // メモリマップを取得
     let mut memory_map = MemoryMapHolder::new();
@@ -230,199 +368,291 @@ pub extern "C" fn efi_main(
         .boot_services
         .get_memory_map(&mut memory_map);
     if status != EfiStatus::Success {
-        output_writer.write_str("Failed to get memory map");
         panic!("Failed to get memory map: {:?}", status);
     }
-    // メモリマップの情報を表示
-
-    // ルートディレクトリを取得
-    let mut root: *mut EfiFileProtocol = null_mut::<EfiFileProtocol>();
-    let status = open_root_dir(image_handle, efi_system_table, &mut root);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open root directory");
-        panic!("Failed to open root directory: {:?}", status);
-    }
-    let root = unsafe { &*root };
-
-    // memmapファイルを開く
-    let mut memmap_file: *mut EfiFileProtocol = null_mut::<EfiFileProtocol>();
-    let status = get_memmap_file(efi_system_table, root, &mut memmap_file);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to get memory map file");
-        panic!("Failed to get memory map file: {:?}", status);
-    }
-    // memmap_fileをEfiFileProtocolにキャスト
-    let memmap_file = unsafe { &*memmap_file };
-
-    // メモリマップをファイルに書き込む
-    let _ = memmap_file.write_str("Test memory map file\n");
-    write_to_memmap_file(&memmap_file, &memory_map);
-
-    // memmapファイルを閉じる
-    let status = memmap_file.close();
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to close memory map file");
-        panic!("Failed to close memory map file: {:?}", status);
+    memory_map.sort_by_address();
+    let totals = memory_map.totals();
+    info!(
+        "Memory: usable {} MiB, boot services {} MiB, ACPI {} KiB, reserved {} MiB",
+        totals.usable >> 20,
+        totals.boot_services >> 20,
+        totals.acpi >> 10,
+        totals.reserved >> 20
+    );
+    if let Some(region) = memory_map.largest_conventional_region() {
+        info!(
+            "Largest free region: 0x{:X} - 0x{:X} ({} MiB)",
+            region.start,
+            region.end,
+            region.size() >> 20
+        );
     }
-    output_writer.write_str("Successfully closed memory map file status: ");
-    output_writer.write_str(status.to_string());
-    output_writer.write_str("\n");
 
-    // カーネルを展開する
-    let mut kernel_file: *mut EfiFileProtocol = null_mut::<EfiFileProtocol>();
-    let status = get_kernel_file(efi_system_table, root, &mut kernel_file);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to open kernel.elf");
-        panic!("Failed to open kernel.elf: {:?}", status);
+    // memmapファイルに書き出す
+    if config.dump_memmap {
+        let memmap_path = config.memmap_path();
+        let result = root.create(memmap_path).and_then(|mut memmap_file| {
+            export_memory_map(&mut memmap_file, &memory_map, config.memmap_format)
+        });
+        match result {
+            Ok(()) => {
+                info!("Wrote memory map to {memmap_path}");
+            }
+            // 書き出せなくても起動はできるので、警告だけにする
+            Err(e) => {
+                warn!("Failed to write memory map to {memmap_path}: {e:?}");
+            }
+        }
     }
-    let kernel_file = unsafe { &*memmap_file };
 
-    // カーネル情報を取得
-    let mut file_info_size: usize = size_of::<EfiFileInfo>();
-    let file_info_buffer: *mut EfiFileInfo = null_mut::<EfiFileInfo>();
-    output_writer.write_str("getting information for kernel.elf ...\n");
-    let status = kernel_file.get_info(&EFI_FILE_INFO_GUID, &mut file_info_size, file_info_buffer);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to get information for kernel.elf\n");
-        panic!(
-            "Failed to get information for kernel.elf status: {:?}",
-            status
+    // カーネルファイルをプールに読み込む
+    // initrdは、カーネルと同じボリュームから読み込む
+    let FoundFile {
+        root: kernel_root,
+        file: mut kernel_file,
+    } = match get_kernel_file(image_handle, efi_system_table, &mut root, entry.path) {
+        Ok(found) => found,
+        Err(e) => {
+            splash.show_error("Failed to open the kernel file", e.status());
+            panic!("Failed to open kernel file: {:?}", e);
+        }
+    };
+    let kernel_image = match kernel_file.read_to_end_with_progress(
+        efi_system_table.boot_services,
+        EfiMemoryType::LOADER_DATA,
+        |read, size| splash.kernel_read(read, size),
+    ) {
+        Ok(image) => image,
+        Err(e) => {
+            splash.show_error("Failed to read the kernel file", e.status());
+            panic!("Failed to read kernel file to pool: {:?}", e);
+        }
+    };
+    drop(kernel_file);
+
+    // 読み込んだカーネルがELFファイルとして正しいかを検証する
+    let kernel_elf = match ElfImage::parse(kernel_image) {
+        Ok(elf) => elf,
+        Err(e) => {
+            splash.show_error("The kernel is not a valid ELF file", e.status());
+            panic!("kernel.elf is not a valid ELF file: {:?}", e);
+        }
+    };
+    let entry_point_addr = kernel_elf.header().e_entry;
+    let phdr_num = kernel_elf.header().e_phnum;
+    info!("kernel entry point address: 0x{entry_point_addr:0>8X} program header num: {phdr_num}");
+
+    for phdr in kernel_elf.load_segments() {
+        let vaddr = phdr.p_vaddr;
+        let filesz = phdr.p_filesz;
+        let memsz = phdr.p_memsz;
+        debug!(
+            "Program Header: vaddr: 0x{vaddr:X}, file size: 0x{filesz:X}, mem size: 0x{memsz:X}"
         );
     }
-    output_writer.write_str("success to get information for kernel.elf\n");
-    let file_info_buffer = unsafe { &*file_info_buffer };
-    let mut kernel_file_size: usize = file_info_buffer.file_size as usize;
 
-    // カーネルを展開する場所を確保する。
-    let mut kernel_buffer = null_mut::<EfiVoid>();
+    // プログラムを読み込む
+    // 位置独立なカーネルなら、メモリマップから空いている領域を探して展開する
+    let loaded_kernel = if kernel_elf.is_relocatable() {
+        let status = efi_system_table
+            .boot_services
+            .get_memory_map(&mut memory_map);
+        if status != EfiStatus::Success {
+            panic!("Failed to get memory map: {:?}", status);
+        }
+        memory_map.sort_by_address();
+        // KASLRが有効なら、空き領域の中からランダムに展開先を選ぶ
+        let random_base = if config.kaslr {
+            match random_u64(efi_system_table) {
+                Some((random, source)) => {
+                    info!("KASLR: entropy from {}", source.to_string());
+                    pick_load_base(
+                        &memory_map,
+                        kernel_elf.load_size(),
+                        kernel_elf.load_align(),
+                        KERNEL_MIN_LOAD_ADDRESS,
+                        random,
+                    )
+                }
+                None => {
+                    warn!("KASLR: no entropy source, using lowest address");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let base = match random_base.or_else(|| {
+            memory_map.find_free_range(
+                kernel_elf.load_size(),
+                kernel_elf.load_align(),
+                KERNEL_MIN_LOAD_ADDRESS,
+            )
+        }) {
+            Some(base) => base,
+            None => {
+                splash.show_error(
+                    "No free memory to load the kernel",
+                    EfiStatus::OutOfResources,
+                );
+                panic!("No free memory to load kernel.elf");
+            }
+        };
+        kernel_elf.load_at(efi_system_table.boot_services, base, |loaded, total| {
+            splash.segment_loaded(loaded, total)
+        })
+    } else {
+        // 固定アドレスのカーネルは、リンク時のアドレスが空いていなければ展開できない
+        let (start, end) = kernel_elf.load_address_range();
+        if !memory_map.is_range_free(start, end - start) {
+            warn!("Kernel range 0x{start:X} - 0x{end:X} is not free memory");
+        }
+        kernel_elf.load(efi_system_table.boot_services, |loaded, total| {
+            splash.segment_loaded(loaded, total)
+        })
+    };
+    let loaded_kernel = match loaded_kernel {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            splash.show_error("Failed to load the kernel", e.status());
+            panic!("Failed to load kernel.elf: {:?}", e);
+        }
+    };
+    let entry_point_addr = loaded_kernel.entry as usize;
+    let kernel_base = loaded_kernel.start;
+    info!("kernel loaded at 0x{kernel_base:X}");
+
+    // カーネルに渡す情報を用意する
+    // ブートサービス終了後はメモリを確保できないので、ここで確保しておく
+    let mut boot_info_buffer = null_mut::<EfiVoid>();
     let status = efi_system_table.boot_services.allocate_pool(
         EfiMemoryType::LOADER_DATA,
-        kernel_file_size,
-        &mut kernel_buffer as *mut *mut EfiVoid,
+        size_of::<BootInfo>(),
+        &mut boot_info_buffer as *mut *mut EfiVoid,
     );
     if status != EfiStatus::Success {
-        output_writer.write_str("Failed to allocate pool");
-        panic!("Failed to allocate pool");
-    }
-    let status = kernel_file.read(&mut kernel_file_size, kernel_buffer);
-    if status != EfiStatus::Success {
-        output_writer.write_str("Failed to read kernel file to pool");
-        panic!("Failed to read kernel file to pool");
+        panic!("Failed to allocate pool for boot info: {:?}", status);
     }
-
-    // 展開した最初の部分は、elf file headerなので、それを読み取る
-    let mut kernel_ehdr: &mut ElfEhdr = unsafe { &mut *(kernel_buffer as *mut ElfEhdr) };
-    let entry_point_addr = kernel_ehdr.e_entry;
-    let phdr_num = kernel_ehdr.e_phnum;
-    let _ = writeln!(
-        buf_writer,
-        "kernel entry point address: 0x{entry_point_addr:0>8X} program header num: {phdr_num}"
-    );
-    output_writer.write_str(buf_writer.as_str().unwrap());
-    buf_writer.flush();
-
-    let phdr_addr: usize = (kernel_ehdr as *mut _ as usize + kernel_ehdr.e_phoff as usize);
-    // プログラムヘッダーの配列を、プログラムヘッダーが書かれている先頭のアドレスから、読み込む。
-    // プログラムヘッダの個数は、e_phnum
-    let phdr_bytes = size_of::<ElfPhdr>() * kernel_ehdr.e_phnum as usize;
-    let mut phdrs = unsafe {
-        core::slice::from_raw_parts_mut(phdr_addr as *mut ElfPhdr, kernel_ehdr.e_phnum as usize)
+    let boot_info_ptr = boot_info_buffer as *mut BootInfo;
+    let mut boot_info = BootInfo::new(FrameBufferConfig::from(gop.mode));
+    panic::set_frame_buffer(boot_info.frame_buffer);
+    boot_info.kernel = MemoryRange {
+        base: loaded_kernel.start,
+        size: loaded_kernel.end - loaded_kernel.start,
     };
+    boot_info.kernel_slide = loaded_kernel.slide;
+    // KASLRで使ったものとは別に、カーネル用の乱数の種を用意する
+    boot_info.entropy_seed = random_u64(efi_system_table).map_or(0, |(seed, _)| seed);
+
+    // ファームウェアが用意したACPIとSMBIOSのテーブルの位置を渡す
+    // ACPI 2.0以降のRSDPがなければ、ACPI 1.0のものを使う
+    boot_info.acpi_rsdp = efi_system_table
+        .find_configuration_table(&EFI_ACPI_20_TABLE_GUID)
+        .or_else(|| efi_system_table.find_configuration_table(&EFI_ACPI_TABLE_GUID))
+        .unwrap_or(0);
+    boot_info.smbios = efi_system_table
+        .find_configuration_table(&SMBIOS_TABLE_GUID)
+        .unwrap_or(0);
+    boot_info.smbios3 = efi_system_table
+        .find_configuration_table(&SMBIOS3_TABLE_GUID)
+        .unwrap_or(0);
+    info!(
+        "ACPI RSDP: 0x{:X}, SMBIOS: 0x{:X}, SMBIOS3: 0x{:X}",
+        boot_info.acpi_rsdp, boot_info.smbios, boot_info.smbios3
+    );
 
-    // プログラムを読み込む
-    for phdr in phdrs {
-        // let phdr = phdrs[i as usize];
-        if phdr.p_type != ElfPhdrType::PtLoad {
-            continue;
-        }
-        // プログラムヘッダーが実際に書かれている場所は、ファイルの先頭アドレスから、offsetの位置に書かれている。
-        // これを読み込んで、virtual addr上に展開する。
-        let offset = phdr.p_offset as usize;
-        let mut vaddr = phdr.p_vaddr;
-        let memsz = phdr.p_memsz as usize;
-        let _ = writeln!(
-            buf_writer,
-            "Program Header: vaddr: 0x{vaddr:X}, mem size: 0x{memsz:X}"
-        );
-        let status = efi_system_table
-            .boot_services
-            .allocate_pages((memsz + 0xfff) / 0x1000, vaddr as *mut u64);
-        if status != EfiStatus::Success {
-            output_writer.write_str("Failed to allocate page for elf program.");
-            panic!("Failed to allocate page for elf program.");
+    // コマンドラインを渡す。コピーできなくても、コマンドラインなしで起動する
+    if !cmdline.is_empty() {
+        match copy_cmdline(efi_system_table.boot_services, cmdline) {
+            Ok(range) => boot_info.cmdline = range,
+            Err(e) => {
+                warn!("Failed to pass cmdline: {e:?}");
+            }
         }
+    }
 
-        // allocateした部分にカーネルの内容をコピーする
-        unsafe {
-            core::ptr::copy(
-                (kernel_buffer as usize + offset) as *const u8,
-                vaddr as *mut u8,
-                memsz,
-            );
+    // initrdを読み込む。読み込めなくても、initrdなしで起動する
+    if let Some(initrd_path) = config.initrd_path {
+        match load_initrd(efi_system_table, &kernel_root, initrd_path) {
+            Ok(initrd) => {
+                info!(
+                    "initrd: {initrd_path} at 0x{:X}, {} bytes",
+                    initrd.base, initrd.size
+                );
+                boot_info.initrd = initrd;
+            }
+            Err(e) => {
+                warn!("Failed to load initrd {initrd_path}: {e:?}");
+            }
         }
     }
-    output_writer.write_str(buf_writer.as_str().unwrap());
-    buf_writer.flush();
+    drop(kernel_root);
+
+    // ここまでのログをカーネルに渡し、\boot.logを閉じる
+    // どちらもメモリマップを変えるので、最終的なメモリマップを取得する前に行う
+    info!("Exiting boot services");
+    match log::copy_to_pool(efi_system_table.boot_services) {
+        Ok(boot_log) => boot_info.boot_log = boot_log,
+        Err(e) => warn!("Failed to pass boot log: {e:?}"),
+    }
+    log::close_file();
 
-    let entry_point_addr = kernel_ehdr.e_entry as usize;
+    // 最終的なメモリマップを取得する
+    // これ以降はメモリを確保しないので、取得したmap_keyがexit_boot_servicesまで有効なままになる
+    let status = efi_system_table
+        .boot_services
+        .get_memory_map(&mut memory_map);
+    if status != EfiStatus::Success {
+        panic!("Failed to get memory map: {:?}", status);
+    }
+
+    // 一度exit_boot_servicesを呼ぶと、失敗してもGetMemoryMapとExitBootServices以外は呼べなくなる
+    // これ以降のログとパニックは、ブートサービスを使わないようにする(シリアルポートとフレームバッファだけ)
+    log::exit_boot_services();
+    panic::exit_boot_services();
 
     // START: EFIのブートサービスを終了する
     let status = efi_system_table
         .boot_services
         .exit_boot_services(image_handle, memory_map.map_key);
     if status != EfiStatus::Success {
+        // 同じバッファで取得し直す。ここでプールを確保すると、map_keyが再び無効になってしまう
         let mut status = efi_system_table
             .boot_services
-            .get_memory_map(&mut memory_map);
+            .refresh_memory_map(&mut memory_map);
         if status != EfiStatus::Success {
-            output_writer.write_str("failed to get memory map: ");
-            output_writer.write_str(status.to_string());
-            output_writer.write_str("\n");
-            loop {}
+            panic!("failed to get memory map: {}", status.to_string());
         }
         status = efi_system_table
             .boot_services
             .exit_boot_services(image_handle, memory_map.map_key);
         if status != EfiStatus::Success {
-            output_writer.write_str("Cloud not exit boot service: ");
-            output_writer.write_str(status.to_string());
-            output_writer.write_str("\n");
-            loop {}
+            panic!("Cloud not exit boot service: {}", status.to_string());
         }
     }
     // END
 
+    // 最終的なメモリマップをカーネルに渡す
+    // memory_mapのバッファはLOADER_DATAのプールにあるので、そのまま渡せる
+    boot_info.memory_map = MemoryMapInfo::new(
+        memory_map.buffer as u64,
+        memory_map.memory_map_size as u64,
+        memory_map.descriptor_size as u64,
+        memory_map.descriptor_version,
+    );
+    unsafe { boot_info_ptr.write(boot_info) };
+
     // エントリーポイントを読み込む
-    let _ = writeln!(buf_writer, "entry address: 0x{entry_point_addr:X}");
-    output_writer.write_str(buf_writer.as_str().unwrap());
-    buf_writer.flush();
+    debug!("entry address: 0x{entry_point_addr:X}");
 
     // エントリーアドレスを関数として実行する
-    output_writer.write_str("execute kernel entry point\n");
-    let entry_point: EntryPointType = unsafe { core::mem::transmute(entry_point_addr) };
-    let entry_point_addr = entry_point as usize;
-    let _ = writeln!(buf_writer, "{entry_point_addr:X}");
-    output_writer.write_str(buf_writer.as_str().expect(""));
-    buf_writer.flush();
-
-    let _ = entry_point(
-        gop.mode.frame_buffer_base,
-        gop.mode.info.pixels_per_scan_line,
-        gop.mode.info.horizontal_resolution,
-        gop.mode.info.vertical_resolution,
-        gop.mode.info.pixel_format as i32,
-    );
-    output_writer.write_str("ALL DONE.\n");
-    loop {}
-    EfiStatus::Success
+    debug!("execute kernel entry point");
+    let entry_point: KernelEntryPoint = unsafe { core::mem::transmute(entry_point_addr) };
+
+    entry_point(boot_info_ptr)
 }
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    // Enter an infinite loop
-    loop {
-        unsafe {
-            asm!("hlt");
-        }
-    }
+fn panic(info: &PanicInfo) -> ! {
+    panic::handle_panic(info)
 }
@@ -0,0 +1,118 @@
+// メモリマップをファイルに書き出す
+// ファームウェアごとのメモリマップを、ホスト側のスクリプトで比較できるようにする
+use core::fmt::Write;
+
+use crate::config::MemmapFormat;
+use crate::memory_map_holder::MemoryMapHolder;
+use crate::stack::BufWriter;
+use crate::uefi::file::File;
+use crate::uefi::types::{Error, Result};
+
+// バイナリ形式の先頭に置くマジックナンバー
+pub const MEMMAP_DUMP_MAGIC: [u8; 8] = *b"MIKANMM\0";
+pub const MEMMAP_DUMP_VERSION: u32 = 1;
+
+// バイナリ形式のヘッダ。値はすべてリトルエンディアンで、この後に記述子がmap_sizeバイト続く
+//   0: magic              [u8; 8]
+//   8: version            u32
+//  12: descriptor_version u32
+//  16: descriptor_size    u64
+//  24: map_size           u64
+pub const MEMMAP_DUMP_HEADER_SIZE: usize = 32;
+
+pub fn export_memory_map(
+    file: &mut File,
+    memory_map: &MemoryMapHolder,
+    format: MemmapFormat,
+) -> Result<()> {
+    match format {
+        MemmapFormat::Csv => write_csv(file, memory_map)?,
+        MemmapFormat::Json => write_json(file, memory_map)?,
+        MemmapFormat::Binary => write_binary(file, memory_map)?,
+    }
+    file.flush()
+}
+
+// 1行ずつバッファに書式化してから、まとめて書き込む
+fn write_line(
+    file: &mut File,
+    buf_writer: &mut BufWriter,
+    args: core::fmt::Arguments,
+) -> Result<()> {
+    buf_writer.flush();
+    buf_writer
+        .write_fmt(args)
+        .map_err(|_| Error::Failed("memory map line is too long"))?;
+    file.write_all(buf_writer.as_str().unwrap().as_bytes())
+}
+
+fn write_csv(file: &mut File, memory_map: &MemoryMapHolder) -> Result<()> {
+    let mut line_buffer = [0u8; 256];
+    let mut buf_writer = BufWriter::new(&mut line_buffer);
+
+    file.write_all(b"Index,Type,TypeName,PhysicalStart,PhysicalEnd,NumberOfPages,Attribute\n")?;
+    for (i, desc) in memory_map.iter().enumerate() {
+        write_line(
+            file,
+            &mut buf_writer,
+            format_args!(
+                "{},{},{},0x{:08X},0x{:08X},{},0x{:X}\n",
+                i,
+                desc.memory_type().0,
+                desc.get_memory_type_str(),
+                desc.physical_start,
+                desc.physical_end(),
+                desc.number_of_pages,
+                desc.attribute
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+// 64ビットのアドレスはJSONの数値では正確に表せないことがあるので、16進数の文字列にする
+fn write_json(file: &mut File, memory_map: &MemoryMapHolder) -> Result<()> {
+    let mut line_buffer = [0u8; 256];
+    let mut buf_writer = BufWriter::new(&mut line_buffer);
+
+    write_line(
+        file,
+        &mut buf_writer,
+        format_args!(
+            "{{\n  \"descriptor_size\": {},\n  \"descriptor_version\": {},\n  \"descriptors\": [\n",
+            memory_map.descriptor_size, memory_map.descriptor_version
+        ),
+    )?;
+    let count = memory_map.iter().count();
+    for (i, desc) in memory_map.iter().enumerate() {
+        write_line(
+            file,
+            &mut buf_writer,
+            format_args!(
+                "    {{\"index\": {}, \"type\": {}, \"type_name\": \"{}\", \
+                 \"physical_start\": \"0x{:X}\", \"physical_end\": \"0x{:X}\", \
+                 \"number_of_pages\": {}, \"attribute\": \"0x{:X}\"}}{}\n",
+                i,
+                desc.memory_type().0,
+                desc.get_memory_type_str(),
+                desc.physical_start,
+                desc.physical_end(),
+                desc.number_of_pages,
+                desc.attribute,
+                if i + 1 < count { "," } else { "" }
+            ),
+        )?;
+    }
+    file.write_all(b"  ]\n}\n")
+}
+
+fn write_binary(file: &mut File, memory_map: &MemoryMapHolder) -> Result<()> {
+    let mut header = [0u8; MEMMAP_DUMP_HEADER_SIZE];
+    header[0..8].copy_from_slice(&MEMMAP_DUMP_MAGIC);
+    header[8..12].copy_from_slice(&MEMMAP_DUMP_VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&memory_map.descriptor_version.to_le_bytes());
+    header[16..24].copy_from_slice(&(memory_map.descriptor_size as u64).to_le_bytes());
+    header[24..32].copy_from_slice(&(memory_map.memory_map_size as u64).to_le_bytes());
+    file.write_all(&header)?;
+    file.write_all(memory_map.as_bytes())
+}
@@ -1,9 +1,16 @@
-use crate::uefi::memory::EfiMemoryDescriptor;
+use core::iter::Peekable;
 
-pub const MEMORY_MAP_BUFFER_SIZE: usize = 4096 * 4; // 4096バイトのページを4つ分
+use crate::uefi::memory::{EfiMemoryDescriptor, EfiMemoryType};
+
+// バッファが足りなかったときに、報告されたサイズに加えて確保する記述子の数
+// バッファをプールに確保すること自体で、記述子が増えることがあるため
+pub const MEMORY_MAP_HEADROOM_DESCRIPTORS: usize = 8;
 
 pub struct MemoryMapHolder {
-    pub memory_map_buffer: [u8; MEMORY_MAP_BUFFER_SIZE],
+    // get_memory_mapがプールに確保するLOADER_DATAのバッファ。カーネルにもそのまま渡す
+    pub buffer: *mut u8,
+    pub buffer_size: usize,
+    // 取得したメモリマップのバイト数
     pub memory_map_size: usize,
     pub map_key: usize,
     // EfiMemoryDescriptorのサイズを取得するのではなく、ここで指定する
@@ -23,14 +30,17 @@ impl<'a> Iterator for MemoryMapIterator<'a> {
     type Item = &'a EfiMemoryDescriptor;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.offset >= self.map.memory_map_size {
+        // descriptor_sizeが壊れていると、同じ記述子を返し続けたり、バッファの外を読んだりするので、
+        // 何も返さない。最後の記述子が途中で切れている場合も、そこで終わりにする
+        if !self.map.has_valid_descriptor_size()
+            || self.offset + self.map.descriptor_size > self.map.memory_map_size
+        {
             None
         } else {
             let e: &EfiMemoryDescriptor = unsafe {
-                // memory_map_bufferはu8の配列なので、
-                // ポインタをEfiMemoryDescriptorにキャストして、offset分だけずらす
-                &*(self.map.memory_map_buffer.as_ptr().add(self.offset)
-                    as *const EfiMemoryDescriptor)
+                // bufferはu8のポインタなので、
+                // offset分だけずらしてから、EfiMemoryDescriptorにキャストする
+                &*(self.map.buffer.add(self.offset) as *const EfiMemoryDescriptor)
             };
             self.offset += self.map.descriptor_size; // 次の行へ進む
             Some(e)
@@ -38,21 +48,203 @@ impl<'a> Iterator for MemoryMapIterator<'a> {
     }
 }
 
+// 隣り合う同じ種類の記述子をまとめたメモリ領域
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub memory_type: EfiMemoryType,
+    pub start: u64,
+    // 領域の終わり(この領域に含まれない最初のアドレス)
+    pub end: u64,
+    pub attribute: u64,
+}
+
+impl MemoryRegion {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+// メモリの種類ごとの合計バイト数
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryTotals {
+    // CONVENTIONAL_MEMORY
+    pub usable: u64,
+    // ブートローダとブートサービスが使っている領域。ブートサービスの終了後は空き領域として使える
+    pub boot_services: u64,
+    // ACPI_RECLAIM_MEMORYとACPI_MEMORY_NVS
+    pub acpi: u64,
+    // それ以外の、使ってはいけない領域
+    pub reserved: u64,
+}
+
+// 隣り合う同じ種類の記述子をまとめながら、領域を順に返すイテレータ
+// 記述子がアドレス順に並んでいないと、まとめられない領域が残る
+pub struct MemoryRegionIterator<'a> {
+    descriptors: Peekable<MemoryMapIterator<'a>>,
+}
+
+impl Iterator for MemoryRegionIterator<'_> {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.descriptors.next()?;
+        let mut region = MemoryRegion {
+            memory_type: first.memory_type(),
+            start: first.physical_start,
+            end: first.physical_end(),
+            attribute: first.attribute,
+        };
+        while let Some(next) = self.descriptors.next_if(|desc| {
+            desc.memory_type() == region.memory_type
+                && desc.attribute == region.attribute
+                && desc.physical_start == region.end
+        }) {
+            region.end = next.physical_end();
+        }
+        Some(region)
+    }
+}
+
 impl MemoryMapHolder {
     pub const fn new() -> MemoryMapHolder {
         MemoryMapHolder {
-            memory_map_buffer: [0; MEMORY_MAP_BUFFER_SIZE],
-            memory_map_size: MEMORY_MAP_BUFFER_SIZE,
+            buffer: core::ptr::null_mut(),
+            buffer_size: 0,
+            memory_map_size: 0,
             map_key: 0,
             descriptor_size: core::mem::size_of::<EfiMemoryDescriptor>(),
             descriptor_version: 0,
         }
     }
 
+    // 取得したメモリマップの部分だけを返す
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.buffer.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.buffer, self.memory_map_size) }
+    }
+
+    // CONVENTIONAL_MEMORYの各領域(隣り合う記述子はまとめる)について、
+    // min_addr以上でalignに揃えたsizeバイトの領域を置ける
+    // 先頭アドレスの候補を、(最も低い候補, 候補の数)として返す。候補はalignごとに並んでいる
+    pub fn free_range_candidates(
+        &self,
+        size: u64,
+        align: u64,
+        min_addr: u64,
+    ) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.regions()
+            .filter(|region| region.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+            .filter_map(move |region| {
+                let start = region.start.max(min_addr).checked_add(align - 1)? & !(align - 1);
+                let end = start.checked_add(size)?;
+                if end <= region.end {
+                    Some((start, (region.end - end) / align + 1))
+                } else {
+                    None
+                }
+            })
+    }
+
+    // 空き領域の候補の中で、最も低いアドレスを返す
+    pub fn find_free_range(&self, size: u64, align: u64, min_addr: u64) -> Option<u64> {
+        self.free_range_candidates(size, align, min_addr)
+            .map(|(start, _)| start)
+            .min()
+    }
+
     pub fn iter(&self) -> MemoryMapIterator {
         MemoryMapIterator {
             map: self,
             offset: 0,
         }
     }
+
+    // 隣り合う同じ種類の記述子をまとめた領域を返す。先にsort_by_addressで並べておく
+    pub fn regions(&self) -> MemoryRegionIterator<'_> {
+        MemoryRegionIterator {
+            descriptors: self.iter().peekable(),
+        }
+    }
+
+    // 記述子を先頭アドレスの順に並べ替える
+    // 記述子の大きさはdescriptor_sizeなので、バッファ上で記述子ごと入れ替える
+    pub fn sort_by_address(&mut self) {
+        if !self.has_valid_descriptor_size() {
+            return;
+        }
+        let size = self.descriptor_size;
+        let count = self.memory_map_size / size;
+        for i in 1..count {
+            let mut j = i;
+            while j > 0
+                && self.descriptor_at(j - 1).physical_start > self.descriptor_at(j).physical_start
+            {
+                unsafe {
+                    core::ptr::swap_nonoverlapping(
+                        self.buffer.add((j - 1) * size),
+                        self.buffer.add(j * size),
+                        size,
+                    );
+                }
+                j -= 1;
+            }
+        }
+    }
+
+    fn has_valid_descriptor_size(&self) -> bool {
+        self.descriptor_size >= size_of::<EfiMemoryDescriptor>()
+    }
+
+    fn descriptor_at(&self, index: usize) -> &EfiMemoryDescriptor {
+        unsafe { &*(self.buffer.add(index * self.descriptor_size) as *const EfiMemoryDescriptor) }
+    }
+
+    pub fn totals(&self) -> MemoryTotals {
+        let mut totals = MemoryTotals::default();
+        for desc in self.iter() {
+            let total = match desc.memory_type() {
+                EfiMemoryType::CONVENTIONAL_MEMORY => &mut totals.usable,
+                EfiMemoryType::LOADER_CODE
+                | EfiMemoryType::LOADER_DATA
+                | EfiMemoryType::BOOT_SERVICES_CODE
+                | EfiMemoryType::BOOT_SERVICES_DATA => &mut totals.boot_services,
+                EfiMemoryType::ACPI_RECLAIM_MEMORY | EfiMemoryType::ACPI_MEMORY_NVS => {
+                    &mut totals.acpi
+                }
+                _ => &mut totals.reserved,
+            };
+            *total += desc.size();
+        }
+        totals
+    }
+
+    // 最も大きい空き領域
+    pub fn largest_conventional_region(&self) -> Option<MemoryRegion> {
+        self.regions()
+            .filter(|region| region.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+            .max_by_key(|region| region.size())
+    }
+
+    // [start, start + size)の範囲がすべてCONVENTIONAL_MEMORYならtrue
+    // 複数の記述子にまたがっていてもよい
+    pub fn is_range_free(&self, start: u64, size: u64) -> bool {
+        let Some(end) = start.checked_add(size) else {
+            return false;
+        };
+        let mut cursor = start;
+        while cursor < end {
+            let containing = self.iter().find(|desc| {
+                desc.memory_type() == EfiMemoryType::CONVENTIONAL_MEMORY
+                    && desc.physical_start <= cursor
+                    && cursor < desc.physical_end()
+            });
+            match containing {
+                Some(desc) => cursor = desc.physical_end(),
+                None => return false,
+            }
+        }
+        true
+    }
 }
@@ -0,0 +1,241 @@
+// 起動時のブートメニュー
+// 上下キーで項目を選び、Enterで起動する。eでコマンドラインを編集できる。
+// 何かキーを押すまでカウントダウンし、0になったら選択中の項目で起動する。
+use core::fmt::Write;
+
+use crate::config::BootEntry;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::text::{
+    EFI_BLACK, EFI_LIGHTGRAY, EFI_WHITE, EfiInputKey, EfiSimpleTextInputProtocol,
+    EfiSimpleTextOutputProtocolWriter, SCAN_DOWN, SCAN_ESC, SCAN_UP, text_attr,
+};
+use crate::uefi::types::{EfiEvent, EfiStatus, EfiTimerDelay, Error, Result};
+
+// 編集したコマンドラインを入れるバッファの推奨サイズ
+pub const CMDLINE_BUFFER_SIZE: usize = 256;
+
+// SetTimerの単位は100ns
+const ONE_SECOND: u64 = 10_000_000;
+
+const CHAR_BACKSPACE: u16 = 0x08;
+const CHAR_CARRIAGE_RETURN: u16 = 0x0d;
+const CHAR_EDIT: u16 = b'e' as u16;
+
+const NORMAL_ATTR: usize = text_attr(EFI_LIGHTGRAY, EFI_BLACK);
+const SELECTED_ATTR: usize = text_attr(EFI_BLACK, EFI_LIGHTGRAY);
+const TITLE_ATTR: usize = text_attr(EFI_WHITE, EFI_BLACK);
+
+// 項目の一覧を表示し始める行
+const FIRST_ENTRY_ROW: usize = 2;
+
+// ブートメニューを表示し、選ばれた項目のインデックスとコマンドラインを返す
+// コマンドラインを編集した場合は、cmdline_bufferに書き込んだ文字列を返す
+// timeoutがNoneのときは、カウントダウンせずにキー入力を待つ
+pub fn show_boot_menu<'a>(
+    system_table: &EfiSystemTable,
+    entries: &[BootEntry<'a>],
+    timeout: Option<u32>,
+    cmdline_buffer: &'a mut [u8],
+) -> Result<(usize, &'a str)> {
+    if entries.is_empty() {
+        return Err(Error::Failed("no boot entries"));
+    }
+    let bs = system_table.boot_services;
+    let con_out = system_table.con_out();
+    let mut writer = EfiSimpleTextOutputProtocolWriter::new(con_out);
+
+    let timer = bs.create_timer_event()?;
+    let result = run_menu(
+        system_table,
+        &mut writer,
+        entries,
+        timeout,
+        timer,
+        cmdline_buffer,
+    );
+
+    let _ = bs.close_event(timer);
+    let _ = con_out.set_attribute(NORMAL_ATTR);
+    let _ = con_out.clear_screen();
+    let _ = con_out.enable_cursor(true);
+
+    let (selected, edited) = result?;
+    let cmdline = match edited {
+        Some(len) => core::str::from_utf8(&cmdline_buffer[..len])
+            .map_err(|_| Error::Failed("cmdline is not valid UTF-8"))?,
+        None => entries[selected].cmdline,
+    };
+    Ok((selected, cmdline))
+}
+
+// 項目が選ばれるまでキー入力を処理する
+// 選ばれた項目のインデックスと、編集した場合はcmdline_bufferに書き込んだバイト数を返す
+fn run_menu(
+    system_table: &EfiSystemTable,
+    writer: &mut EfiSimpleTextOutputProtocolWriter,
+    entries: &[BootEntry],
+    timeout: Option<u32>,
+    timer: EfiEvent,
+    cmdline_buffer: &mut [u8],
+) -> Result<(usize, Option<usize>)> {
+    let bs = system_table.boot_services;
+    let con_in = system_table.con_in();
+    let con_out = writer.protocol;
+
+    if timeout.is_some() {
+        bs.set_timer(timer, EfiTimerDelay::TimerPeriodic, ONE_SECOND)?;
+    }
+    let _ = con_out.enable_cursor(false);
+    con_out.clear_screen()?;
+
+    let mut selected = 0;
+    let mut remaining = timeout;
+    loop {
+        draw_menu(writer, entries, selected, remaining)?;
+
+        let key = match remaining {
+            Some(0) => return Ok((selected, None)),
+            Some(seconds) => {
+                if bs.wait_for_event(&[con_in.wait_for_key, timer])? == 1 {
+                    remaining = Some(seconds - 1);
+                    continue;
+                }
+                // キーが押されたらカウントダウンを止める
+                remaining = None;
+                bs.set_timer(timer, EfiTimerDelay::TimerCancel, 0)?;
+                read_key(system_table, con_in)?
+            }
+            None => read_key(system_table, con_in)?,
+        };
+
+        match (key.scan_code, key.unicode_char) {
+            (SCAN_UP, _) => selected = selected.checked_sub(1).unwrap_or(entries.len() - 1),
+            (SCAN_DOWN, _) => selected = (selected + 1) % entries.len(),
+            (_, CHAR_CARRIAGE_RETURN) => return Ok((selected, None)),
+            (_, CHAR_EDIT) => {
+                let edited = edit_cmdline(
+                    system_table,
+                    writer,
+                    entries[selected].cmdline,
+                    cmdline_buffer,
+                )?;
+                if edited.is_some() {
+                    return Ok((selected, edited));
+                }
+                con_out.clear_screen()?;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw_menu(
+    writer: &mut EfiSimpleTextOutputProtocolWriter,
+    entries: &[BootEntry],
+    selected: usize,
+    remaining: Option<u32>,
+) -> Result<()> {
+    let con_out = writer.protocol;
+    con_out.set_cursor_position(0, 0)?;
+    con_out.set_attribute(TITLE_ATTR)?;
+    writer.write_str("MikanOS boot menu\n");
+
+    for (i, entry) in entries.iter().enumerate() {
+        con_out.set_cursor_position(0, FIRST_ENTRY_ROW + i)?;
+        con_out.set_attribute(if i == selected {
+            SELECTED_ATTR
+        } else {
+            NORMAL_ATTR
+        })?;
+        let _ = write!(writer, "  {}  ", entry.title);
+    }
+
+    con_out.set_attribute(NORMAL_ATTR)?;
+    con_out.set_cursor_position(0, FIRST_ENTRY_ROW + entries.len() + 1)?;
+    let _ = write!(writer, "  {}", entries[selected].cmdline);
+    con_out.set_cursor_position(0, FIRST_ENTRY_ROW + entries.len() + 3)?;
+    match remaining {
+        Some(seconds) => {
+            let _ = write!(writer, "Booting in {seconds} seconds...");
+        }
+        None => writer.write_str("Up/Down: select, Enter: boot, e: edit cmdline"),
+    }
+    // 前の表示の残りを消す
+    writer.write_str("                    ");
+    Ok(())
+}
+
+// キー入力を待って一つ読む
+fn read_key(
+    system_table: &EfiSystemTable,
+    con_in: &EfiSimpleTextInputProtocol,
+) -> Result<EfiInputKey> {
+    let bs = system_table.boot_services;
+    loop {
+        match con_in.read_key_stroke() {
+            Err(Error::EfiError(EfiStatus::NotReady)) => {
+                bs.wait_for_event(&[con_in.wait_for_key])?;
+            }
+            result => return result,
+        }
+    }
+}
+
+// コマンドラインを編集する。Enterで確定したら書き込んだバイト数、Escで取り消したらNoneを返す
+fn edit_cmdline(
+    system_table: &EfiSystemTable,
+    writer: &mut EfiSimpleTextOutputProtocolWriter,
+    initial: &str,
+    buffer: &mut [u8],
+) -> Result<Option<usize>> {
+    let con_out = writer.protocol;
+    let con_in = system_table.con_in();
+
+    // バッファに収まらない分は、文字の境界で切り捨てる
+    let mut len = initial.len().min(buffer.len());
+    while !initial.is_char_boundary(len) {
+        len -= 1;
+    }
+    buffer[..len].copy_from_slice(&initial.as_bytes()[..len]);
+
+    con_out.clear_screen()?;
+    con_out.set_attribute(TITLE_ATTR)?;
+    writer.write_str("Edit cmdline (Enter: boot, Esc: cancel)\n\n");
+    con_out.set_attribute(NORMAL_ATTR)?;
+    writer.write_str("> ");
+    for &c in &buffer[..len] {
+        writer.write_char(c);
+    }
+    let _ = con_out.enable_cursor(true);
+
+    let result = loop {
+        let key = read_key(system_table, con_in)?;
+        match key.unicode_char {
+            CHAR_CARRIAGE_RETURN => break Some(len),
+            CHAR_BACKSPACE => {
+                if len == 0 {
+                    continue;
+                }
+                len -= 1;
+                // マルチバイト文字は、まとめて消す
+                while len > 0 && (buffer[len] & 0xc0) == 0x80 {
+                    len -= 1;
+                }
+                // カーソルを戻すだけでは文字が残るので、空白で上書きしてから戻す
+                writer.write_char(CHAR_BACKSPACE as u8);
+                writer.write_char(b' ');
+                writer.write_char(CHAR_BACKSPACE as u8);
+            }
+            // 表示できるASCII文字だけを受け付ける
+            c @ 0x20..=0x7e if len < buffer.len() => {
+                buffer[len] = c as u8;
+                len += 1;
+                writer.write_char(c as u8);
+            }
+            _ if key.scan_code == SCAN_ESC => break None,
+            _ => {}
+        }
+    };
+    let _ = con_out.enable_cursor(false);
+    Ok(result)
+}
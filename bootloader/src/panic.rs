@@ -0,0 +1,159 @@
+// パニックしたときの処理
+// ブートサービスが使える間は、メッセージを画面(con_outとGOP)とシリアルポートに表示し、
+// キーが押されたら再起動する。押されなければ、しばらく待ってからエラーを返してファームウェアに戻る。
+// ブートサービスの終了後は、GOPのフレームバッファとシリアルポートに表示して止まる。
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use crate::boot_info::FrameBufferConfig;
+use crate::error;
+use crate::font::FONT_HEIGHT;
+use crate::frame_buffer::{Color, FrameBuffer, TextWriter};
+use crate::log;
+use crate::uefi::graphics::EfiGraphicsOutputProtocol;
+use crate::uefi::types::{EfiHandle, EfiStatus};
+use crate::uefi::{EfiResetType, EfiSystemTable};
+
+// キー入力を待つ時間。過ぎたらファームウェアに戻る
+const REBOOT_WAIT_SECONDS: usize = 10;
+// キー入力を確認する間隔(マイクロ秒)
+const POLL_INTERVAL: usize = 100_000;
+
+const PANIC_BG: Color = Color::DARK_RED;
+const PANEL_MARGIN: usize = 16;
+
+struct PanicState {
+    image_handle: EfiHandle,
+    system_table: *const EfiSystemTable,
+    boot_services_active: bool,
+    // ブートサービスが使える間に描くGOP。PixelBltOnlyのモードでも、Bltで描ける
+    gop: Option<&'static EfiGraphicsOutputProtocol<'static>>,
+    frame_buffer: Option<FrameBufferConfig>,
+    panicking: bool,
+}
+
+// log.rsと同じく、シングルスレッドで動くので排他制御をしない
+struct GlobalPanicState(UnsafeCell<PanicState>);
+
+unsafe impl Sync for GlobalPanicState {}
+
+static STATE: GlobalPanicState = GlobalPanicState(UnsafeCell::new(PanicState {
+    image_handle: 0,
+    system_table: core::ptr::null(),
+    boot_services_active: false,
+    gop: None,
+    frame_buffer: None,
+    panicking: false,
+}));
+
+fn state() -> &'static mut PanicState {
+    unsafe { &mut *STATE.0.get() }
+}
+
+// efi_mainの最初に呼ぶ。これより前のパニックでは、何も表示せずに止まる
+pub fn init(image_handle: EfiHandle, system_table: &EfiSystemTable) {
+    let state = state();
+    state.image_handle = image_handle;
+    state.system_table = system_table;
+    state.boot_services_active = true;
+}
+
+// ブートサービスが使える間に表示するGOPを設定する
+pub fn set_gop(gop: &'static EfiGraphicsOutputProtocol<'static>) {
+    state().gop = Some(gop);
+}
+
+// ブートサービスの終了後に表示するフレームバッファを設定する
+pub fn set_frame_buffer(config: FrameBufferConfig) {
+    state().frame_buffer = Some(config);
+}
+
+pub fn exit_boot_services() {
+    let state = state();
+    state.boot_services_active = false;
+    state.gop = None;
+}
+
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    let state = state();
+    // 表示の途中でもう一度パニックした場合は、何もせずに止まる
+    if state.panicking {
+        halt();
+    }
+    state.panicking = true;
+
+    // スプラッシュ画面の表示中でも、メッセージは画面に出す
+    log::set_screen_enabled(true);
+    // logがcon_out(ブートサービスの終了後はなし)とシリアルポートに書き出す
+    match info.location() {
+        Some(location) => error!("panicked at {location}: {}", info.message()),
+        None => error!("panicked: {}", info.message()),
+    }
+
+    if state.boot_services_active && !state.system_table.is_null() {
+        let system_table = unsafe { &*state.system_table };
+        log::close_file();
+        error!(
+            "Press any key to reboot, or wait {REBOOT_WAIT_SECONDS} seconds to return to firmware"
+        );
+        // con_outへの出力が済んでから描く。後の出力で画面がスクロールしないようにする
+        if let Some(frame_buffer) = state.gop.and_then(FrameBuffer::from_gop) {
+            draw_panic(frame_buffer, info);
+        }
+        wait_and_exit(state.image_handle, system_table);
+    }
+    if let Some(frame_buffer) = state.frame_buffer.and_then(FrameBuffer::new) {
+        draw_panic(frame_buffer, info);
+    }
+    halt();
+}
+
+// キーが押されたら再起動し、押されなければファームウェアに戻る
+fn wait_and_exit(image_handle: EfiHandle, system_table: &EfiSystemTable) -> ! {
+    let boot_services = system_table.boot_services;
+    let con_in = system_table.con_in();
+    let _ = con_in.reset();
+    for _ in 0..REBOOT_WAIT_SECONDS * 1_000_000 / POLL_INTERVAL {
+        if con_in.read_key_stroke().is_ok() {
+            system_table
+                .runtime_services
+                .reset_system(EfiResetType::EfiResetCold, EfiStatus::Success);
+        }
+        let _ = boot_services.stall(POLL_INTERVAL);
+    }
+    let _ = boot_services.exit(image_handle, EfiStatus::Aborted);
+    // Exitが失敗した場合は、ここで止まる
+    halt();
+}
+
+// 画面の上部に赤いパネルを描き、メッセージを表示する
+fn draw_panic(mut frame_buffer: FrameBuffer, info: &PanicInfo) {
+    let width = frame_buffer.width();
+    let height = (FONT_HEIGHT * 8 + PANEL_MARGIN * 2).min(frame_buffer.height());
+    frame_buffer.fill_rect(0, 0, width, height, PANIC_BG);
+    let mut writer = TextWriter::new(
+        &mut frame_buffer,
+        (PANEL_MARGIN, PANEL_MARGIN),
+        (
+            width.saturating_sub(PANEL_MARGIN * 2),
+            height.saturating_sub(PANEL_MARGIN * 2),
+        ),
+        Color::WHITE,
+        PANIC_BG,
+    );
+    let _ = writeln!(writer, "Bootloader panicked");
+    if let Some(location) = info.location() {
+        let _ = writeln!(writer, "at {location}");
+    }
+    let _ = writeln!(writer, "{}", info.message());
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("hlt");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// ブートローダで使う乱数の取得
+// EFI_RNG_PROTOCOLが使えればそれを使い、使えなければCPUのRDRAND命令を使う
+use crate::uefi::EfiSystemTable;
+use crate::uefi::rng::EfiRngProtocol;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+
+// RDRANDは一時的に失敗することがあるので、何回か試す
+const RDRAND_RETRY: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropySource {
+    EfiRng,
+    Rdrand,
+}
+
+impl EntropySource {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            EntropySource::EfiRng => "EFI_RNG_PROTOCOL",
+            EntropySource::Rdrand => "RDRAND",
+        }
+    }
+}
+
+fn efi_rng_u64(system_table: &EfiSystemTable) -> Option<u64> {
+    let rng = system_table
+        .boot_services
+        .locate_protocol::<EfiRngProtocol>()
+        .ok()?;
+    rng.get_u64()
+}
+
+pub fn rdrand_u64() -> Option<u64> {
+    // CPUID.01H:ECX.RDRAND[bit 30]
+    if __cpuid(1).ecx & (1 << 30) == 0 {
+        return None;
+    }
+    for _ in 0..RDRAND_RETRY {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack)
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// 64bitの乱数を一つ取得する。どちらも使えない場合はNone
+pub fn random_u64(system_table: &EfiSystemTable) -> Option<(u64, EntropySource)> {
+    if let Some(value) = efi_rng_u64(system_table) {
+        return Some((value, EntropySource::EfiRng));
+    }
+    rdrand_u64().map(|value| (value, EntropySource::Rdrand))
+}
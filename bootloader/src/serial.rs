@@ -0,0 +1,78 @@
+// シリアルポート(16550互換のUART)への出力
+// ブートサービスの終了後も使えるので、ログとパニックの出力先にする
+use core::arch::asm;
+use core::fmt;
+
+pub const COM1_PORT: u16 = 0x3f8;
+
+// 各レジスタの、ベースのポートからのオフセット
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// LINE_STATUSのビット。送信バッファが空いている
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+// 送信バッファが空くのを待つ回数の上限。UARTが応答しなくても止まらないようにする
+const TRANSMIT_RETRY: usize = 100_000;
+
+fn outb(port: u16, value: u8) {
+    unsafe {
+        asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack));
+    }
+}
+
+fn inb(port: u16) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack));
+    }
+    value
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    // 115200bps, 8N1で初期化する。UARTがない場合はNone
+    pub fn init(base: u16) -> Option<SerialPort> {
+        // 存在しないポートを読むと、すべてのビットが1になる
+        if inb(base + LINE_STATUS) == 0xff {
+            return None;
+        }
+        outb(base + INTERRUPT_ENABLE, 0x00);
+        // DLABを立てて、分周比を1にする
+        outb(base + LINE_CONTROL, 0x80);
+        outb(base + DATA, 0x01);
+        outb(base + INTERRUPT_ENABLE, 0x00);
+        outb(base + LINE_CONTROL, 0x03);
+        outb(base + FIFO_CONTROL, 0xc7);
+        outb(base + MODEM_CONTROL, 0x0b);
+        Some(SerialPort { base })
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        for _ in 0..TRANSMIT_RETRY {
+            if inb(self.base + LINE_STATUS) & LINE_STATUS_THR_EMPTY != 0 {
+                break;
+            }
+        }
+        outb(self.base + DATA, byte);
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+// 起動中のスプラッシュ画面
+// GOPのフレームバッファにロゴと進捗バーを描き、カーネルを読み込んで展開するにつれて進捗バーを伸ばす。
+// 失敗したときは、赤いパネルにメッセージとEfiStatusの名前を表示する。
+// 表示している間、ログは画面(con_out)に出さない。パニックしたときは、panic.rsが元に戻す。
+use core::fmt::Write;
+
+use crate::bmp::Bmp;
+use crate::font::{FONT_HEIGHT, FONT_WIDTH};
+use crate::frame_buffer::{Color, FrameBuffer, TextWriter};
+use crate::log;
+use crate::uefi::graphics::EfiGraphicsOutputProtocol;
+use crate::uefi::types::EfiStatus;
+
+// ロゴとして読み込む最大のバイト数
+pub const LOGO_FILE_MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+// 進捗のうち、カーネルファイルの読み込みが占める割合(%)。残りはセグメントの展開
+const READ_PERCENT: usize = 80;
+
+const BAR_HEIGHT: usize = 8;
+const BAR_COLOR: Color = Color::WHITE;
+const BAR_BORDER_COLOR: Color = Color::GRAY;
+const PANEL_MARGIN: usize = 16;
+const PANEL_LINES: usize = 3;
+
+pub struct Splash<'a> {
+    // スプラッシュ画面を表示していないときはNone。そのときは何もしない
+    frame_buffer: Option<FrameBuffer<'a>>,
+    // 進捗バーの内側の位置と大きさ
+    bar_x: usize,
+    bar_y: usize,
+    bar_width: usize,
+    // 進捗バーのうち、塗り終わった幅
+    filled: usize,
+}
+
+impl<'a> Splash<'a> {
+    // 何も表示しないスプラッシュ画面
+    pub fn disabled() -> Splash<'a> {
+        Splash {
+            frame_buffer: None,
+            bar_x: 0,
+            bar_y: 0,
+            bar_width: 0,
+            filled: 0,
+        }
+    }
+
+    // 画面を消して、ロゴ、進捗バーの枠、起動する項目の名前を描く
+    // ロゴは画面の中央より少し上に置き、その下に進捗バーと名前を並べる
+    pub fn new(
+        gop: &'a EfiGraphicsOutputProtocol<'a>,
+        logo: Option<&Bmp>,
+        title: &str,
+    ) -> Splash<'a> {
+        let Some(mut frame_buffer) = FrameBuffer::from_gop(gop) else {
+            return Splash::disabled();
+        };
+        let (width, height) = (frame_buffer.width(), frame_buffer.height());
+        frame_buffer.fill_rect(0, 0, width, height, Color::BLACK);
+
+        if let Some(logo) = logo {
+            let x = width.saturating_sub(logo.width()) / 2;
+            let y = (height / 2).saturating_sub(logo.height());
+            for dy in 0..logo.height() {
+                for dx in 0..logo.width() {
+                    frame_buffer.write_pixel(x + dx, y + dy, logo.pixel(dx, dy));
+                }
+            }
+        }
+
+        let bar_width = width / 3;
+        let bar_x = (width - bar_width) / 2;
+        let bar_y = height / 2 + FONT_HEIGHT * 2;
+        frame_buffer.fill_rect(
+            bar_x - 1,
+            bar_y - 1,
+            bar_width + 2,
+            BAR_HEIGHT + 2,
+            BAR_BORDER_COLOR,
+        );
+        frame_buffer.fill_rect(bar_x, bar_y, bar_width, BAR_HEIGHT, Color::BLACK);
+        let title_x = width.saturating_sub(title.len() * FONT_WIDTH) / 2;
+        frame_buffer.draw_str(
+            title_x,
+            bar_y + BAR_HEIGHT + FONT_HEIGHT,
+            title,
+            Color::WHITE,
+            Color::BLACK,
+        );
+
+        // テキストのログが、描いた画面の上に重ならないようにする
+        log::set_screen_enabled(false);
+        Splash {
+            frame_buffer: Some(frame_buffer),
+            bar_x,
+            bar_y,
+            bar_width,
+            filled: 0,
+        }
+    }
+
+    // カーネルファイルをreadバイトまで読み込んだ
+    pub fn kernel_read(&mut self, read: usize, size: usize) {
+        self.set_percent(READ_PERCENT * read / size.max(1));
+    }
+
+    // カーネルのセグメントをloaded個まで展開した
+    pub fn segment_loaded(&mut self, loaded: usize, total: usize) {
+        self.set_percent(READ_PERCENT + (100 - READ_PERCENT) * loaded / total.max(1));
+    }
+
+    // 進捗バーを伸ばす。前回より短くなる場合は何もしない
+    fn set_percent(&mut self, percent: usize) {
+        let Some(frame_buffer) = self.frame_buffer.as_mut() else {
+            return;
+        };
+        let filled = self.bar_width * percent.min(100) / 100;
+        if filled <= self.filled {
+            return;
+        }
+        frame_buffer.fill_rect(
+            self.bar_x + self.filled,
+            self.bar_y,
+            filled - self.filled,
+            BAR_HEIGHT,
+            BAR_COLOR,
+        );
+        self.filled = filled;
+    }
+
+    // 進捗バーの下に赤いパネルを描き、messageとstatusの名前を表示する
+    pub fn show_error(&mut self, message: &str, status: EfiStatus) {
+        let Some(frame_buffer) = self.frame_buffer.as_mut() else {
+            return;
+        };
+        let width = frame_buffer.width();
+        let y = self.bar_y + BAR_HEIGHT + FONT_HEIGHT * 3;
+        let height = FONT_HEIGHT * PANEL_LINES + PANEL_MARGIN * 2;
+        frame_buffer.fill_rect(0, y, width, height, Color::DARK_RED);
+        let mut writer = TextWriter::new(
+            frame_buffer,
+            (PANEL_MARGIN, y + PANEL_MARGIN),
+            (
+                width.saturating_sub(PANEL_MARGIN * 2),
+                height - PANEL_MARGIN * 2,
+            ),
+            Color::WHITE,
+            Color::DARK_RED,
+        );
+        let _ = writeln!(writer, "{message}");
+        let _ = writeln!(
+            writer,
+            "EFI status: {} (0x{:X})",
+            status.to_string(),
+            status.0
+        );
+        let _ = writeln!(
+            writer,
+            "See {} or the serial console for details",
+            log::LOG_FILE_PATH
+        );
+    }
+}
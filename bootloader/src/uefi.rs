@@ -1,19 +1,57 @@
+pub mod device_path;
 pub mod file;
 pub mod graphics;
 pub mod memory;
+pub mod rng;
 pub mod text;
 pub mod types;
 
 use core::marker::PhantomPinned;
-use core::mem::offset_of;
+use core::mem::{offset_of, size_of};
+use core::ops::Deref;
 use core::ptr::null_mut;
 
-use crate::memory_map_holder::MemoryMapHolder;
+use crate::memory_map_holder::{MEMORY_MAP_HEADROOM_DESCRIPTORS, MemoryMapHolder};
 use crate::uefi::memory::{EfiAllocateType, EfiMemoryType};
+use device_path::EfiDevicePathProtocol;
 use graphics::*;
-use text::EfiSimpleTextOutputProtocol;
+use text::{EfiSimpleTextInputProtocol, EfiSimpleTextOutputProtocol};
 use types::*;
 
+/// GUIDで識別されるプロトコル
+///
+/// # Safety
+/// 実装する型は、GUIDが表すプロトコルのインターフェースと同じレイアウトでなければならない
+pub unsafe trait Protocol {
+    const GUID: EfiGuid;
+}
+
+// locate_handlesで取得したハンドルの配列。ドロップするときにプールを解放する
+pub struct HandleBuffer<'a> {
+    boot_services: &'a EfiBootServicesTable,
+    handles: *mut EfiHandle,
+    len: usize,
+}
+
+impl Deref for HandleBuffer<'_> {
+    type Target = [EfiHandle];
+
+    fn deref(&self) -> &[EfiHandle] {
+        if self.handles.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.handles, self.len) }
+    }
+}
+
+impl Drop for HandleBuffer<'_> {
+    fn drop(&mut self) {
+        if !self.handles.is_null() {
+            let _ = self.boot_services.free_pool(self.handles as *mut EfiVoid);
+        }
+    }
+}
+
 // https://github.com/tianocore/edk2/blob/8216419a02173421ce7070268fdd11a7caadfa4b/MdePkg/Include/Uefi/UefiSpec.h#L2021
 #[repr(C)]
 pub struct EfiBootServicesTable {
@@ -24,7 +62,7 @@ pub struct EfiBootServicesTable {
         pages: usize,
         memory: *mut u64,
     ) -> EfiStatus,
-    _reserved1: [u64; 1],
+    free_pages: extern "win64" fn(memory: u64, pages: usize) -> EfiStatus,
     get_memory_map: extern "win64" fn(
         // メモリマップを書き込む用のバッファのサイズを設定。小さすぎるとエラーとなる。
         memory_map_size: *mut usize,
@@ -45,9 +83,54 @@ pub struct EfiBootServicesTable {
         buffer: *mut *mut EfiVoid,
     ) -> EfiStatus,
     free_pool: extern "win64" fn(buffer: *mut EfiVoid) -> EfiStatus,
-    _reserved3: [u64; 19],
+    create_event: extern "win64" fn(
+        event_type: u32,
+        notify_tpl: usize,
+        notify_function: *const EfiVoid,
+        notify_context: *const EfiVoid,
+        event: *mut EfiEvent,
+    ) -> EfiStatus,
+    set_timer: extern "win64" fn(
+        event: EfiEvent,
+        timer_type: EfiTimerDelay,
+        // 100ns単位
+        trigger_time: u64,
+    ) -> EfiStatus,
+    wait_for_event: extern "win64" fn(
+        number_of_events: usize,
+        event: *const EfiEvent,
+        index: *mut usize,
+    ) -> EfiStatus,
+    _reserved2: [u64; 1],
+    close_event: extern "win64" fn(event: EfiEvent) -> EfiStatus,
+    _reserved3: [u64; 10],
+    load_image: extern "win64" fn(
+        // trueのときは、ブートマネージャからの起動として扱われる
+        boot_policy: bool,
+        parent_image_handle: EfiHandle,
+        device_path: *const EfiDevicePathProtocol,
+        // NULLでなければ、device_pathから読む代わりにこのバッファをイメージとして使う
+        source_buffer: *const EfiVoid,
+        source_size: usize,
+        image_handle: *mut EfiHandle,
+    ) -> EfiStatus,
+    start_image: extern "win64" fn(
+        image_handle: EfiHandle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> EfiStatus,
+    exit: extern "win64" fn(
+        image_handle: EfiHandle,
+        exit_status: EfiStatus,
+        exit_data_size: usize,
+        exit_data: *const u16,
+    ) -> EfiStatus,
+    unload_image: extern "win64" fn(image_handle: EfiHandle) -> EfiStatus,
     exit_boot_services: extern "win64" fn(image_handle: EfiHandle, map_key: usize) -> EfiStatus,
-    _reserved4: [u64; 5],
+    _reserved4: [u64; 1],
+    // マイクロ秒単位
+    stall: extern "win64" fn(microseconds: usize) -> EfiStatus,
+    _reserved5: [u64; 3],
     // https://github.com/tianocore/edk2/blob/562bce0febd641f78df7cd61f2ed5a4c944b31ac/MdePkg/Include/Uefi/UefiSpec.h#L2021
     open_protocol: extern "win64" fn(
         handle: EfiHandle,
@@ -57,7 +140,7 @@ pub struct EfiBootServicesTable {
         controller_handle: EfiHandle,
         attributes: u32,
     ) -> EfiStatus,
-    _reserved5: [u64; 3],
+    _reserved6: [u64; 3],
     locate_handle_buffer: extern "win64" fn(
         search_type: EfiLocateSearchType,
         protocol: *const EfiGuid,
@@ -74,14 +157,57 @@ pub struct EfiBootServicesTable {
 
 impl EfiBootServicesTable {
     // EFI APIのメモリマップ取得APIからメモリマップを取得して、mapに格納する
+    // バッファが足りなければ、報告されたサイズに余裕を持たせたバッファをプールに確保し直して、もう一度取得する
     pub fn get_memory_map(&self, map: &mut MemoryMapHolder) -> EfiStatus {
-        (self.get_memory_map)(
-            &mut map.memory_map_size,
-            map.memory_map_buffer.as_mut_ptr(), // memory_map_bufferはu8の配列なので、ポインタに変換して、先頭のポインタを渡す
+        loop {
+            let required_size = match self.get_memory_map_into(map) {
+                Ok(()) => return EfiStatus::Success,
+                Err((EfiStatus::BufferTooSmall, required_size)) => required_size,
+                Err((status, _)) => return status,
+            };
+            if !map.buffer.is_null() {
+                let _ = self.free_pool(map.buffer);
+                map.buffer = null_mut();
+                map.buffer_size = 0;
+            }
+            let size = required_size + MEMORY_MAP_HEADROOM_DESCRIPTORS * map.descriptor_size;
+            let mut buffer = null_mut::<EfiVoid>();
+            let status = self.allocate_pool(EfiMemoryType::LOADER_DATA, size, &mut buffer);
+            if status != EfiStatus::Success {
+                return status;
+            }
+            map.buffer = buffer;
+            map.buffer_size = size;
+        }
+    }
+
+    // 今のバッファのままメモリマップを取得し直す。プールを確保しないので、取得したmap_keyが無効にならない
+    // exit_boot_servicesに失敗した後の再取得に使う
+    pub fn refresh_memory_map(&self, map: &mut MemoryMapHolder) -> EfiStatus {
+        match self.get_memory_map_into(map) {
+            Ok(()) => EfiStatus::Success,
+            Err((status, _)) => status,
+        }
+    }
+
+    // 失敗したときは、ステータスと必要なバッファのサイズを返す
+    fn get_memory_map_into(
+        &self,
+        map: &mut MemoryMapHolder,
+    ) -> core::result::Result<(), (EfiStatus, usize)> {
+        let mut size = map.buffer_size;
+        let status = (self.get_memory_map)(
+            &mut size,
+            map.buffer,
             &mut map.map_key,
             &mut map.descriptor_size,
             &mut map.descriptor_version,
-        )
+        );
+        if status != EfiStatus::Success {
+            return Err((status, size));
+        }
+        map.memory_map_size = size;
+        Ok(())
     }
 
     pub fn allocate_pages(&self, pages: usize, memory: *mut u64) -> EfiStatus {
@@ -93,6 +219,23 @@ impl EfiBootServicesTable {
         )
     }
 
+    // 任意のアドレスに、memory_typeのページを確保して、その先頭の物理アドレスを返す
+    pub fn allocate_any_pages(&self, memory_type: EfiMemoryType, pages: usize) -> Result<u64> {
+        let mut memory = 0;
+        (self.allocate_pages)(
+            EfiAllocateType::AllocateAnyPages,
+            memory_type,
+            pages,
+            &mut memory,
+        )
+        .into_result()?;
+        Ok(memory)
+    }
+
+    pub fn free_pages(&self, memory: u64, pages: usize) -> EfiStatus {
+        (self.free_pages)(memory, pages)
+    }
+
     pub fn allocate_pool(
         &self,
         pool_type: EfiMemoryType,
@@ -104,42 +247,187 @@ impl EfiBootServicesTable {
     pub fn free_pool(&self, buffer: *mut EfiVoid) -> EfiStatus {
         (self.free_pool)(buffer)
     }
-    pub fn locate_handle_buffer(
-        &self,
-        search_key: EfiLocateSearchType,
-        protocol: *const EfiGuid,
-        no_handles: *mut usize,
-        buffer: *mut *mut EfiHandle,
-    ) -> EfiStatus {
+    // プロトコルTをサポートするハンドルをすべて取得する
+    pub fn locate_handles<T: Protocol>(&self) -> Result<HandleBuffer<'_>> {
+        let mut len: usize = 0;
+        let mut handles = null_mut::<EfiHandle>();
         (self.locate_handle_buffer)(
-            search_key,
-            protocol,
+            EfiLocateSearchType::ByProtocol,
+            &T::GUID,
             null_mut::<EfiVoid>(),
-            no_handles,
-            buffer,
+            &mut len,
+            &mut handles,
         )
+        .into_result()?;
+        Ok(HandleBuffer {
+            boot_services: self,
+            handles,
+            len,
+        })
     }
-    pub fn open_protocol(
+
+    // handleが持つプロトコルTのインターフェースを取得する
+    pub fn open_protocol<T: Protocol>(
         &self,
         handle: EfiHandle,
-        protocol: *const EfiGuid,
-        interface: *mut *mut EfiVoid,
         agent_handle: EfiHandle,
-        controller_handle: EfiHandle,
-    ) -> EfiStatus {
+    ) -> Result<&T> {
+        Ok(unsafe { &*self.open_protocol_ptr(handle, agent_handle)? })
+    }
+
+    // インターフェースのフィールドを書き換えるときに使う
+    // (起動する子イメージのLoadedImageProtocolに引数を設定するときなど)
+    pub fn open_protocol_ptr<T: Protocol>(
+        &self,
+        handle: EfiHandle,
+        agent_handle: EfiHandle,
+    ) -> Result<*mut T> {
+        let mut interface = null_mut::<EfiVoid>();
         (self.open_protocol)(
             handle,
-            protocol,
-            interface,
+            &T::GUID,
+            &mut interface,
             agent_handle,
-            controller_handle,
+            0,
             EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL,
         )
+        .into_result()?;
+        if interface.is_null() {
+            return Err(Error::Failed("protocol interface is null"));
+        }
+        Ok(interface as *mut T)
+    }
+
+    // プロトコルTを持つハンドルを一つ探し、そのインターフェースを取得する
+    pub fn locate_protocol<T: Protocol>(&self) -> Result<&T> {
+        let mut interface = null_mut::<EfiVoid>();
+        (self.locate_protocol)(&T::GUID, null_mut::<EfiVoid>(), &mut interface).into_result()?;
+        if interface.is_null() {
+            return Err(Error::Failed("protocol interface is null"));
+        }
+        Ok(unsafe { &*(interface as *const T) })
+    }
+
+    // タイマーイベントを作る。通知関数は使わず、wait_for_eventで待つ
+    pub fn create_timer_event(&self) -> Result<EfiEvent> {
+        let mut event: EfiEvent = 0;
+        (self.create_event)(
+            EVT_TIMER,
+            0,
+            core::ptr::null(),
+            core::ptr::null(),
+            &mut event,
+        )
+        .into_result()?;
+        Ok(event)
+    }
+
+    pub fn set_timer(
+        &self,
+        event: EfiEvent,
+        timer_type: EfiTimerDelay,
+        trigger_time: u64,
+    ) -> Result<()> {
+        (self.set_timer)(event, timer_type, trigger_time).into_result()
+    }
+
+    // eventsのどれかがシグナル状態になるまで待ち、そのインデックスを返す
+    pub fn wait_for_event(&self, events: &[EfiEvent]) -> Result<usize> {
+        let mut index: usize = 0;
+        (self.wait_for_event)(events.len(), events.as_ptr(), &mut index).into_result()?;
+        Ok(index)
+    }
+
+    pub fn close_event(&self, event: EfiEvent) -> Result<()> {
+        (self.close_event)(event).into_result()
     }
 
     pub fn exit_boot_services(&self, handle: EfiHandle, map_key: usize) -> EfiStatus {
         (self.exit_boot_services)(handle, map_key)
     }
+
+    // イメージを終了して、statusをファームウェアに返す。成功した場合は戻らない
+    pub fn exit(&self, image_handle: EfiHandle, status: EfiStatus) -> EfiStatus {
+        (self.exit)(image_handle, status, 0, core::ptr::null())
+    }
+
+    pub fn stall(&self, microseconds: usize) -> Result<()> {
+        (self.stall)(microseconds).into_result()
+    }
+
+    // sourceをイメージとして読み込み、イメージのハンドルを返す
+    // device_pathは、読み込んだイメージのLoadedImageProtocolのファイルパスになる
+    pub fn load_image(
+        &self,
+        parent_image_handle: EfiHandle,
+        device_path: *const EfiDevicePathProtocol,
+        source: &[u8],
+    ) -> Result<EfiHandle> {
+        let mut image_handle: EfiHandle = 0;
+        let status = (self.load_image)(
+            false,
+            parent_image_handle,
+            device_path,
+            source.as_ptr(),
+            source.len(),
+            &mut image_handle,
+        );
+        // セキュリティの検証に失敗した場合も、イメージは読み込まれているので解放する
+        if status.is_error() && image_handle != 0 {
+            let _ = self.unload_image(image_handle);
+        }
+        status.into_result()?;
+        Ok(image_handle)
+    }
+
+    // イメージを実行し、イメージが終了したときの終了コードを返す
+    // 終了したアプリケーションは、ファームウェアがアンロードする
+    pub fn start_image(&self, image_handle: EfiHandle) -> EfiStatus {
+        let mut exit_data_size = 0;
+        let mut exit_data = null_mut::<u16>();
+        let status = (self.start_image)(image_handle, &mut exit_data_size, &mut exit_data);
+        // 終了時のメッセージは使わないので、解放する
+        if !exit_data.is_null() {
+            let _ = self.free_pool(exit_data as *mut EfiVoid);
+        }
+        status
+    }
+
+    // 読み込んだまま実行しなかったイメージを解放する
+    pub fn unload_image(&self, image_handle: EfiHandle) -> Result<()> {
+        (self.unload_image)(image_handle).into_result()
+    }
+}
+
+// https://uefi.org/specs/UEFI/2.10/08_Services_Runtime_Services.html#resetsystem
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EfiResetType {
+    EfiResetCold = 0,
+    EfiResetWarm,
+    EfiResetShutdown,
+    EfiResetPlatformSpecific,
+}
+
+// https://github.com/tianocore/edk2/blob/8216419a02173421ce7070268fdd11a7caadfa4b/MdePkg/Include/Uefi/UefiSpec.h#L1873
+// ブートサービスの終了後も使える
+#[repr(C)]
+pub struct EfiRuntimeServicesTable {
+    _header: [u64; 3],
+    _reserved0: [u64; 10],
+    reset_system: extern "win64" fn(
+        reset_type: EfiResetType,
+        reset_status: EfiStatus,
+        data_size: usize,
+        reset_data: *const EfiVoid,
+    ) -> !,
+}
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, reset_system) == 104);
+
+impl EfiRuntimeServicesTable {
+    pub fn reset_system(&self, reset_type: EfiResetType, status: EfiStatus) -> ! {
+        (self.reset_system)(reset_type, status, 0, core::ptr::null())
+    }
 }
 
 // https://uefi.org/specs/UEFI/2.10/04_EFI_System_Table.html#id6
@@ -148,19 +436,59 @@ pub struct EfiSystemTable {
     _header: [u64; 3],
     _firmware_vendor: EfiHandle,
     _firmware_revision: u32, // この後に、4バイトのパディングがある
-    _reserved0: [u64; 3],
+    _console_in_handle: EfiHandle,
+    pub con_in: &'static EfiSimpleTextInputProtocol,
+    _console_out_handle: EfiHandle,
     pub con_out: &'static EfiSimpleTextOutputProtocol,
-    _reserved1: [u64; 3],
+    _standard_error_handle: EfiHandle,
+    pub std_err: &'static EfiSimpleTextOutputProtocol,
+    pub runtime_services: &'static EfiRuntimeServicesTable,
     pub boot_services: &'static EfiBootServicesTable,
+    pub number_of_table_entries: usize,
+    pub configuration_table: *const EfiConfigurationTable,
 }
 
 impl EfiSystemTable {
+    pub fn con_in(&self) -> &'static EfiSimpleTextInputProtocol {
+        self.con_in
+    }
+
     pub fn con_out(&self) -> &'static EfiSimpleTextOutputProtocol {
         self.con_out
     }
+
+    pub fn configuration_tables(&self) -> &[EfiConfigurationTable] {
+        if self.configuration_table.is_null() {
+            return &[];
+        }
+        unsafe {
+            core::slice::from_raw_parts(self.configuration_table, self.number_of_table_entries)
+        }
+    }
+
+    // guidに対応するテーブルの物理アドレス
+    pub fn find_configuration_table(&self, guid: &EfiGuid) -> Option<u64> {
+        self.configuration_tables()
+            .iter()
+            .find(|table| table.vendor_guid == *guid)
+            .map(|table| table.vendor_table as u64)
+    }
 }
 
+const _: () = assert!(offset_of!(EfiSystemTable, con_in) == 48);
+const _: () = assert!(offset_of!(EfiSystemTable, std_err) == 80);
+const _: () = assert!(offset_of!(EfiSystemTable, runtime_services) == 88);
 const _: () = assert!(offset_of!(EfiSystemTable, boot_services) == 96);
+const _: () = assert!(offset_of!(EfiSystemTable, configuration_table) == 112);
+
+// ファームウェアが用意したテーブル(ACPI, SMBIOSなど)の一覧の要素
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EfiConfigurationTable {
+    pub vendor_guid: EfiGuid,
+    pub vendor_table: *const EfiVoid,
+}
+const _: () = assert!(size_of::<EfiConfigurationTable>() == 24);
 
 // https://github.com/tianocore/edk2/blob/562bce0febd641f78df7cd61f2ed5a4c944b31ac/MdePkg/Include/Protocol/LoadedImage.h#L43
 #[repr(C)]
@@ -168,45 +496,62 @@ const _: () = assert!(offset_of!(EfiSystemTable, boot_services) == 96);
 pub struct EfiLoadedImageProtocol {
     _padding0: [u64; 3],
     pub device_handle: EfiHandle,
+    _file_path: EfiHandle,
+    _reserved: u64,
+    // load_optionsのバイト数
+    pub load_options_size: u32,
+    pub load_options: *const EfiVoid,
     _pinned: PhantomPinned,
 }
 const _: () = assert!(offset_of!(EfiLoadedImageProtocol, device_handle) == 24);
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, load_options_size) == 48);
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, load_options) == 56);
+
+impl EfiLoadedImageProtocol {
+    // シェルやブートマネージャから渡された引数。UCS-2の文字列であることが多いが、
+    // ブートオプションによっては任意のバイナリが入っている
+    pub fn load_options(&self) -> &[u16] {
+        if self.load_options.is_null() {
+            return &[];
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                self.load_options as *const u16,
+                self.load_options_size as usize / size_of::<u16>(),
+            )
+        }
+    }
+}
+
+unsafe impl Protocol for EfiLoadedImageProtocol {
+    const GUID: EfiGuid = EFI_LOADED_IMAGE_PROTOCOL_GUID;
+}
 
 // locate_protocolのオフセットを確認するためのアサーション(オフセットは、バイトで計算する)
+const _: () = assert!(offset_of!(EfiBootServicesTable, free_pages) == 48);
 const _: () = assert!(offset_of!(EfiBootServicesTable, get_memory_map) == 56);
+const _: () = assert!(offset_of!(EfiBootServicesTable, create_event) == 80);
+const _: () = assert!(offset_of!(EfiBootServicesTable, close_event) == 112);
+const _: () = assert!(offset_of!(EfiBootServicesTable, load_image) == 200);
+const _: () = assert!(offset_of!(EfiBootServicesTable, start_image) == 208);
+const _: () = assert!(offset_of!(EfiBootServicesTable, exit) == 216);
+const _: () = assert!(offset_of!(EfiBootServicesTable, unload_image) == 224);
+const _: () = assert!(offset_of!(EfiBootServicesTable, exit_boot_services) == 232);
+const _: () = assert!(offset_of!(EfiBootServicesTable, stall) == 248);
 const _: () = assert!(offset_of!(EfiBootServicesTable, locate_protocol) == 320);
 
 pub fn open_gop<'a>(
     image_handle: EfiHandle,
     system_table: &EfiSystemTable,
 ) -> Result<&'a EfiGraphicsOutputProtocol<'a>> {
-    let mut graphic_output_protocol = null_mut::<EfiGraphicsOutputProtocol>();
-    let mut num_gop_handles: usize = 0;
-    let mut gop_handles = null_mut::<EfiHandle>();
-
-    let status = system_table.boot_services.locate_handle_buffer(
-        EfiLocateSearchType::ByProtocol,
-        &EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID,
-        &mut num_gop_handles as *mut usize,
-        &mut gop_handles as *mut *mut EfiHandle,
-    );
-    if status != EfiStatus::Success {
-        return Err(Error::Failed("Failed to locate graphics output protocol"));
-    }
-    let gop_handles_array = unsafe { core::slice::from_raw_parts(gop_handles, num_gop_handles) };
-
-    let status = system_table.boot_services.open_protocol(
-        gop_handles_array[0],
-        &EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID,
-        &mut graphic_output_protocol as *mut *mut EfiGraphicsOutputProtocol as *mut *mut EfiVoid,
-        image_handle,
-        null_mut::<u64>() as u64,
-    );
-    if status != EfiStatus::Success {
-        return Err(Error::Failed("Failed to open graphics output protocol"));
-    }
-
-    let _ = system_table.boot_services.free_pool(gop_handles as *mut u8);
-
-    Ok(unsafe { &*graphic_output_protocol })
+    let boot_services: &'static EfiBootServicesTable = system_table.boot_services;
+    let gop_handles = boot_services
+        .locate_handles::<EfiGraphicsOutputProtocol>()
+        .map_err(|_| Error::Failed("Failed to locate graphics output protocol"))?;
+    let gop_handle = *gop_handles
+        .first()
+        .ok_or(Error::Failed("No graphics output protocol found"))?;
+    boot_services
+        .open_protocol::<EfiGraphicsOutputProtocol>(gop_handle, image_handle)
+        .map_err(|_| Error::Failed("Failed to open graphics output protocol"))
 }
@@ -0,0 +1,203 @@
+// EFI_DEVICE_PATH_PROTOCOL
+// デバイスパスは可変長のノードの並びで、End of Entire Device Pathのノードで終わる
+// https://uefi.org/specs/UEFI/2.10/10_Protocols_Device_Path_Protocol.html
+use core::fmt;
+use core::mem::{offset_of, size_of, size_of_val};
+use core::ptr::null_mut;
+
+use crate::uefi::file::{MAX_PATH_LEN, path_to_ucs2};
+use crate::uefi::memory::EfiMemoryType;
+use crate::uefi::types::{
+    EFI_DEVICE_PATH_PROTOCOL_GUID, EFI_DEVICE_PATH_TO_TEXT_PROTOCOL_GUID, EfiGuid, EfiVoid, Error,
+    Result,
+};
+use crate::uefi::{EfiBootServicesTable, Protocol};
+
+// ノードの種類(Type)とサブタイプ(Sub-Type)
+pub const MEDIA_DEVICE_PATH: u8 = 0x04;
+pub const MEDIA_FILEPATH_DP: u8 = 0x04;
+pub const END_DEVICE_PATH_TYPE: u8 = 0x7f;
+pub const END_ENTIRE_DEVICE_PATH_SUBTYPE: u8 = 0xff;
+
+// 一つのノードのヘッダ。この後にノードの種類ごとのデータが続く
+#[repr(C)]
+#[derive(Debug)]
+pub struct EfiDevicePathProtocol {
+    pub device_type: u8,
+    pub sub_type: u8,
+    // ヘッダを含むノードのバイト数(リトルエンディアン)
+    length: [u8; 2],
+}
+
+const _: () = assert!(size_of::<EfiDevicePathProtocol>() == 4);
+
+unsafe impl Protocol for EfiDevicePathProtocol {
+    const GUID: EfiGuid = EFI_DEVICE_PATH_PROTOCOL_GUID;
+}
+
+impl EfiDevicePathProtocol {
+    pub fn node_length(&self) -> usize {
+        u16::from_le_bytes(self.length) as usize
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.device_type == END_DEVICE_PATH_TYPE && self.sub_type == END_ENTIRE_DEVICE_PATH_SUBTYPE
+    }
+
+    // 終端のノードを含まない、デバイスパス全体のバイト数
+    // 長さが壊れているノード(ヘッダより短いもの)があれば、そこで終わりとみなす
+    pub fn size_without_end(&self) -> usize {
+        let mut size = 0;
+        let mut node = self;
+        while !node.is_end() && node.node_length() >= size_of::<EfiDevicePathProtocol>() {
+            size += node.node_length();
+            node = unsafe {
+                &*((node as *const Self as *const u8).add(node.node_length()) as *const Self)
+            };
+        }
+        size
+    }
+}
+
+// プールに作ったデバイスパス。ドロップするときにプールを解放する
+pub struct DevicePathBuffer<'a> {
+    boot_services: &'a EfiBootServicesTable,
+    buffer: *mut EfiVoid,
+}
+
+impl DevicePathBuffer<'_> {
+    pub fn as_ptr(&self) -> *const EfiDevicePathProtocol {
+        self.buffer as *const EfiDevicePathProtocol
+    }
+}
+
+impl Drop for DevicePathBuffer<'_> {
+    fn drop(&mut self) {
+        let _ = self.boot_services.free_pool(self.buffer);
+    }
+}
+
+fn write_node_header(buffer: &mut [u8], device_type: u8, sub_type: u8, length: usize) {
+    buffer[0] = device_type;
+    buffer[1] = sub_type;
+    buffer[2..4].copy_from_slice(&(length as u16).to_le_bytes());
+}
+
+// deviceのデバイスパスの後ろに、pathを指すファイルパスのノードをつなげる
+// LoadImageに、ブートボリューム上のファイルを指定するときに使う
+pub fn file_device_path<'a>(
+    boot_services: &'a EfiBootServicesTable,
+    device: &EfiDevicePathProtocol,
+    path: &str,
+) -> Result<DevicePathBuffer<'a>> {
+    let mut path_buffer = [0u16; MAX_PATH_LEN];
+    let path = path_to_ucs2(path, &mut path_buffer)?;
+    let header_size = size_of::<EfiDevicePathProtocol>();
+    let device_size = device.size_without_end();
+    let file_node_size = header_size + size_of_val(path);
+    if file_node_size > u16::MAX as usize {
+        return Err(Error::Failed("path is too long for a device path"));
+    }
+    let size = device_size + file_node_size + header_size;
+
+    let mut buffer = null_mut::<EfiVoid>();
+    boot_services
+        .allocate_pool(EfiMemoryType::LOADER_DATA, size, &mut buffer)
+        .into_result()?;
+    let bytes = unsafe { core::slice::from_raw_parts_mut(buffer, size) };
+    let device_bytes =
+        unsafe { core::slice::from_raw_parts(device as *const _ as *const u8, device_size) };
+    bytes[..device_size].copy_from_slice(device_bytes);
+
+    let file_node = &mut bytes[device_size..device_size + file_node_size];
+    write_node_header(
+        file_node,
+        MEDIA_DEVICE_PATH,
+        MEDIA_FILEPATH_DP,
+        file_node_size,
+    );
+    for (i, c) in path.iter().enumerate() {
+        let offset = header_size + i * size_of::<u16>();
+        file_node[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+    }
+
+    write_node_header(
+        &mut bytes[device_size + file_node_size..],
+        END_DEVICE_PATH_TYPE,
+        END_ENTIRE_DEVICE_PATH_SUBTYPE,
+        header_size,
+    );
+    Ok(DevicePathBuffer {
+        boot_services,
+        buffer,
+    })
+}
+
+// EFI_DEVICE_PATH_TO_TEXT_PROTOCOL
+// デバイスパスを"PciRoot(0x0)/Pci(0x1,0x1)/Ata(0x0)"のようなテキストに変換する
+#[repr(C)]
+pub struct EfiDevicePathToTextProtocol {
+    _convert_device_node_to_text: u64,
+    convert_device_path_to_text: extern "win64" fn(
+        device_path: *const EfiDevicePathProtocol,
+        // trueのときは、表示用の短い形式にする
+        display_only: bool,
+        // trueのときは、短縮形のノード名を使う
+        allow_shortcuts: bool,
+    ) -> *mut u16,
+}
+
+const _: () = assert!(offset_of!(EfiDevicePathToTextProtocol, convert_device_path_to_text) == 8);
+
+unsafe impl Protocol for EfiDevicePathToTextProtocol {
+    const GUID: EfiGuid = EFI_DEVICE_PATH_TO_TEXT_PROTOCOL_GUID;
+}
+
+impl EfiDevicePathToTextProtocol {
+    pub fn to_text<'a>(
+        &self,
+        boot_services: &'a EfiBootServicesTable,
+        device_path: &EfiDevicePathProtocol,
+    ) -> Result<DevicePathText<'a>> {
+        let text = (self.convert_device_path_to_text)(device_path, false, true);
+        if text.is_null() {
+            return Err(Error::Failed("failed to convert device path to text"));
+        }
+        Ok(DevicePathText {
+            boot_services,
+            text,
+        })
+    }
+}
+
+// ConvertDevicePathToTextが返したNULL終端のUCS-2の文字列。Displayで表示できる
+// ドロップするときにプールを解放する
+pub struct DevicePathText<'a> {
+    boot_services: &'a EfiBootServicesTable,
+    text: *mut u16,
+}
+
+impl DevicePathText<'_> {
+    pub fn as_ucs2(&self) -> &[u16] {
+        let mut len = 0;
+        while unsafe { *self.text.add(len) } != 0 {
+            len += 1;
+        }
+        unsafe { core::slice::from_raw_parts(self.text, len) }
+    }
+}
+
+impl fmt::Display for DevicePathText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in char::decode_utf16(self.as_ucs2().iter().copied()) {
+            fmt::Write::write_char(f, c.unwrap_or(char::REPLACEMENT_CHARACTER))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DevicePathText<'_> {
+    fn drop(&mut self) {
+        let _ = self.boot_services.free_pool(self.text as *mut EfiVoid);
+    }
+}
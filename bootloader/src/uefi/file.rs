@@ -1,14 +1,52 @@
-use crate::uefi::types::{EfiGuid, EfiStatus, EfiTime, EfiVoid};
-use core::mem::offset_of;
-#[repr(C)]
-#[derive(Default, Debug)]
-pub struct EfiFileName {
-    name: [u16; 12],
+use crate::uefi::memory::EfiMemoryType;
+use crate::uefi::types::{
+    EFI_FILE_INFO_GUID, EFI_FILE_MODE_CREATE, EFI_FILE_MODE_READ, EFI_FILE_MODE_WRITE,
+    EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID, EfiGuid, EfiStatus, EfiTime, EfiVoid, Error, Result,
+};
+use crate::uefi::{EfiBootServicesTable, Protocol};
+use core::fmt;
+use core::mem::{ManuallyDrop, offset_of, size_of};
+use core::ptr::{NonNull, null_mut};
+
+// ファイルパスをUCS-2に変換するときのバッファの大きさ(NULL文字を含む)
+pub const MAX_PATH_LEN: usize = 256;
+
+// UTF-8のパスを、EfiFileProtocol::openに渡せるNULL終端のUCS-2に変換する
+// 区切り文字の'/'は'\'に置き換える
+pub fn path_to_ucs2<'a>(path: &str, buffer: &'a mut [u16]) -> Result<&'a [u16]> {
+    let mut len = 0;
+    for c in path.chars() {
+        let c = if c == '/' { '\\' } else { c };
+        // UCS-2で表せない文字(サロゲートペアが必要な文字)とNULL文字は使えない
+        if c == '\0' || c as u32 > 0xffff {
+            return Err(Error::Failed(
+                "path contains a character not representable in UCS-2",
+            ));
+        }
+        // 最後にNULL文字を入れる分を残しておく
+        if len + 1 >= buffer.len() {
+            return Err(Error::Failed("path is too long"));
+        }
+        buffer[len] = c as u16;
+        len += 1;
+    }
+    buffer[len] = 0;
+    Ok(&buffer[..=len])
 }
 
+// EFI_FILE_INFO.Attributeのビット
+pub const EFI_FILE_READ_ONLY: u64 = 0x01;
+pub const EFI_FILE_HIDDEN: u64 = 0x02;
+pub const EFI_FILE_SYSTEM: u64 = 0x04;
+pub const EFI_FILE_DIRECTORY: u64 = 0x10;
+pub const EFI_FILE_ARCHIVE: u64 = 0x20;
+
+// https://uefi.org/specs/UEFI/2.10/13_Protocols_Media_Access.html#efi-file-info
+// FileNameはNULL終端の可変長なので、固定長の部分だけを定義する
 #[repr(C)]
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct EfiFileInfo {
+    // FileNameを含む構造体全体のバイト数
     size: u64,
     pub file_size: u64,
     pub physical_size: u64,
@@ -16,7 +54,114 @@ pub struct EfiFileInfo {
     pub last_access_time: EfiTime,
     pub modification_time: EfiTime,
     pub attr: u64,
-    pub file_name: EfiFileName,
+    file_name: [u16; 0],
+}
+
+const _: () = assert!(offset_of!(EfiFileInfo, file_name) == 80);
+
+// get_infoやディレクトリの読み込みで取得したファイルの情報
+// 名前を含む可変長のEFI_FILE_INFOをプールに持ち、ドロップするときに解放する
+pub struct FileInfo<'a> {
+    boot_services: &'a EfiBootServicesTable,
+    info: NonNull<EfiFileInfo>,
+    // NULL文字を含まない名前の長さ
+    name_len: usize,
+}
+
+impl<'a> FileInfo<'a> {
+    // bufferはallocate_poolで確保したもので、buffer_sizeバイトのEFI_FILE_INFOが入っている
+    fn from_pool(
+        boot_services: &'a EfiBootServicesTable,
+        buffer: *mut EfiVoid,
+        buffer_size: usize,
+    ) -> Result<FileInfo<'a>> {
+        // 以降でエラーを返すときは、infoのドロップでbufferを解放する
+        let mut info = FileInfo {
+            boot_services,
+            info: NonNull::new(buffer as *mut EfiFileInfo)
+                .ok_or(Error::Failed("file info buffer is null"))?,
+            name_len: 0,
+        };
+        if buffer_size < size_of::<EfiFileInfo>() {
+            return Err(Error::Failed("file info is too small"));
+        }
+        let max_name_len = (buffer_size - size_of::<EfiFileInfo>()) / size_of::<u16>();
+        let name = unsafe { core::slice::from_raw_parts(info.name_ptr(), max_name_len) };
+        info.name_len = name
+            .iter()
+            .position(|&c| c == 0)
+            .ok_or(Error::Failed("file name is not NUL-terminated"))?;
+        Ok(info)
+    }
+
+    fn name_ptr(&self) -> *const u16 {
+        unsafe { (self.info.as_ptr() as *const u8).add(size_of::<EfiFileInfo>()) as *const u16 }
+    }
+
+    pub fn info(&self) -> &EfiFileInfo {
+        unsafe { self.info.as_ref() }
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.info().file_size
+    }
+
+    pub fn attribute(&self) -> u64 {
+        self.info().attr
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attribute() & EFI_FILE_DIRECTORY != 0
+    }
+
+    // UCS-2の名前。NULL文字は含まない
+    pub fn name(&self) -> &[u16] {
+        unsafe { core::slice::from_raw_parts(self.name_ptr(), self.name_len) }
+    }
+
+    pub fn file_name(&self) -> FileName<'_> {
+        FileName(self.name())
+    }
+}
+
+impl Drop for FileInfo<'_> {
+    fn drop(&mut self) {
+        let _ = self
+            .boot_services
+            .free_pool(self.info.as_ptr() as *mut EfiVoid);
+    }
+}
+
+// UCS-2のファイル名。Displayで表示できる
+#[derive(Debug, Clone, Copy)]
+pub struct FileName<'a>(pub &'a [u16]);
+
+impl FileName<'_> {
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        char::decode_utf16(self.0.iter().copied()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    // FATに合わせて、ASCIIの大文字と小文字を区別せずに比べる
+    pub fn eq_ignore_ascii_case(&self, name: &str) -> bool {
+        let mut chars = self.chars();
+        name.chars()
+            .all(|c| chars.next().is_some_and(|d| c.eq_ignore_ascii_case(&d)))
+            && chars.next().is_none()
+    }
+
+    pub fn ends_with_ignore_ascii_case(&self, suffix: &str) -> bool {
+        let len = suffix.encode_utf16().count();
+        len <= self.0.len() && FileName(&self.0[self.0.len() - len..]).eq_ignore_ascii_case(suffix)
+    }
+}
+
+impl fmt::Display for FileName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.chars() {
+            fmt::Write::write_char(f, c)?;
+        }
+        Ok(())
+    }
 }
 
 // GUID SIMPLE FILE SYSTEM PROTOCOLの実装
@@ -28,6 +173,10 @@ pub struct EfiSimpleFileSystemProtocol {
         extern "win64" fn(this: *const Self, root: *mut *mut EfiFileProtocol) -> EfiStatus,
 }
 
+unsafe impl Protocol for EfiSimpleFileSystemProtocol {
+    const GUID: EfiGuid = EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID;
+}
+
 impl EfiSimpleFileSystemProtocol {
     pub fn open_volume(&self, root: *mut *mut EfiFileProtocol) -> EfiStatus {
         (self.open_volume)(self as *const Self, root)
@@ -47,7 +196,8 @@ pub struct EfiFileProtocol {
         attributes: u64,
     ) -> EfiStatus,
     pub close: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
-    _reserved0: [u64; 1],
+    // 成功しても失敗しても、ハンドルは閉じられる
+    pub delete: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
     pub read: extern "win64" fn(
         this: *const EfiFileProtocol,
         buffer_size: *mut usize,
@@ -58,14 +208,18 @@ pub struct EfiFileProtocol {
         buffer_size: *mut usize,
         buffer: *mut EfiVoid,
     ) -> EfiStatus,
-    _reserved1: [u64; 2],
+    pub get_position:
+        extern "win64" fn(this: *const EfiFileProtocol, position: *mut u64) -> EfiStatus,
+    pub set_position: extern "win64" fn(this: *mut EfiFileProtocol, position: u64) -> EfiStatus,
     pub get_info: extern "win64" fn(
         this: *const Self,
         information_type: *const EfiGuid,
         buffer_size: *mut usize,
         buffer: *mut EfiVoid,
     ) -> EfiStatus,
-    _reserved2: [u64; 6],
+    _reserved0: [u64; 1],
+    pub flush: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
+    _reserved1: [u64; 4],
 }
 impl EfiFileProtocol {
     pub fn open(
@@ -87,48 +241,362 @@ impl EfiFileProtocol {
     pub fn read(&self, buffer_size: &mut usize, buffer: *mut EfiVoid) -> EfiStatus {
         (self.read)(self as *const Self, buffer_size as *mut usize, buffer)
     }
+    pub fn write(&self, buffer_size: &mut usize, buffer: *const EfiVoid) -> EfiStatus {
+        (self.write)(
+            self as *const _ as *mut EfiFileProtocol,
+            buffer_size as *mut usize,
+            buffer as *mut EfiVoid,
+        )
+    }
     pub fn close(&self) -> EfiStatus {
         (self.close)(self as *const _ as *mut EfiFileProtocol)
     }
+    pub fn delete(&self) -> EfiStatus {
+        (self.delete)(self as *const _ as *mut EfiFileProtocol)
+    }
+    pub fn get_position(&self, position: &mut u64) -> EfiStatus {
+        (self.get_position)(self as *const Self, position as *mut u64)
+    }
+    pub fn set_position(&self, position: u64) -> EfiStatus {
+        (self.set_position)(self as *const _ as *mut EfiFileProtocol, position)
+    }
+    pub fn flush(&self) -> EfiStatus {
+        (self.flush)(self as *const _ as *mut EfiFileProtocol)
+    }
     pub fn get_info(
         &self,
         information_type: &EfiGuid,
         buffer_size: &mut usize,
-        buffer: *mut EfiFileInfo,
+        buffer: *mut EfiVoid,
     ) -> EfiStatus {
         (self.get_info)(
             self as *const _ as *mut EfiFileProtocol,
             information_type as *const EfiGuid,
             buffer_size as *mut usize,
-            buffer as *mut EfiVoid,
+            buffer,
         )
     }
-    pub fn write_char(&self, c: u8) -> EfiStatus {
-        (self.write)(
-            self as *const _ as *mut EfiFileProtocol,
-            &mut 1,                     // 書き込むバイト数
-            &c as *const u8 as *mut u8, // 書き込むバッファのポインタ
+}
+
+const _: () = assert!(offset_of!(EfiSimpleFileSystemProtocol, open_volume) == 8);
+const _: () = assert!(offset_of!(EfiFileProtocol, open) == 8);
+const _: () = assert!(offset_of!(EfiFileProtocol, close) == 16);
+const _: () = assert!(offset_of!(EfiFileProtocol, delete) == 24);
+const _: () = assert!(offset_of!(EfiFileProtocol, write) == 40);
+const _: () = assert!(offset_of!(EfiFileProtocol, set_position) == 56);
+const _: () = assert!(offset_of!(EfiFileProtocol, get_info) == 64);
+const _: () = assert!(offset_of!(EfiFileProtocol, flush) == 80);
+const _: () = assert!(size_of::<EfiFileProtocol>() == 120);
+
+// 一回のWriteで書き込む最大のバイト数
+// 大きすぎる書き込みに失敗するファームウェアがあるので、分割して書き込む
+const WRITE_CHUNK_SIZE: usize = 1024 * 1024;
+// read_to_end_with_progressで、一度に読み込むバイト数
+const READ_CHUNK_SIZE: usize = 256 * 1024;
+
+// SetPositionにこの値を渡すと、ファイルの末尾に移動する
+const END_OF_FILE_POSITION: u64 = u64::MAX;
+
+// seekの基準となる位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+// 所有権を持つファイルハンドル。ドロップするときに閉じる
+pub struct File {
+    handle: NonNull<EfiFileProtocol>,
+}
+
+impl File {
+    // ボリュームのルートディレクトリを開く
+    pub fn open_volume(fs: &EfiSimpleFileSystemProtocol) -> Result<File> {
+        let mut root = null_mut::<EfiFileProtocol>();
+        fs.open_volume(&mut root).into_result()?;
+        File::from_raw(root)
+    }
+
+    // openなどで得た生のハンドルから作る。閉じる責任はFileに移る
+    pub fn from_raw(handle: *mut EfiFileProtocol) -> Result<File> {
+        let handle = NonNull::new(handle).ok_or(Error::Failed("file handle is null"))?;
+        Ok(File { handle })
+    }
+
+    pub fn protocol(&self) -> &EfiFileProtocol {
+        unsafe { self.handle.as_ref() }
+    }
+
+    // このファイル(ディレクトリ)からの相対パスでファイルを開く
+    pub fn open(&self, path: &str, open_mode: u64) -> Result<File> {
+        let mut path_buffer = [0u16; MAX_PATH_LEN];
+        let path = path_to_ucs2(path, &mut path_buffer)?;
+        let mut handle = null_mut::<EfiFileProtocol>();
+        self.protocol()
+            .open(&mut handle, path, open_mode, 0)
+            .into_result()?;
+        File::from_raw(handle)
+    }
+
+    // 空のファイルを作って、読み書きできるように開く
+    // Openには既存のファイルを切り詰める方法がないので、既存のファイルは一度削除する
+    pub fn create(&self, path: &str) -> Result<File> {
+        match self.open(path, EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE) {
+            Ok(file) => file.delete()?,
+            Err(Error::EfiError(EfiStatus::NotFound)) => {}
+            Err(e) => return Err(e),
+        }
+        self.open(
+            path,
+            EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE | EFI_FILE_MODE_CREATE,
         )
     }
 
-    pub fn write_str(&self, s: &str) -> EfiStatus {
-        for c in s.bytes() {
-            if c == b'\n' {
-                let status = self.write_char(b'\r');
-                if status != EfiStatus::Success {
-                    return status; // 改行文字の書き込みに失敗した場合は、エラーを返す
+    // 読み込んだバイト数を返す。0ならファイルの終わり
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        self.protocol()
+            .read(&mut size, buf.as_mut_ptr())
+            .into_result()?;
+        Ok(size)
+    }
+
+    // bufがいっぱいになるまで読み込む
+    pub fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Error::Failed("unexpected end of file")),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    // 現在の位置からファイルの終わりまでを、memory_typeのプールに読み込む
+    // 返したメモリは、呼び出し側がfree_poolで解放するか、そのまま使い続ける
+    pub fn read_to_end(
+        &mut self,
+        boot_services: &EfiBootServicesTable,
+        memory_type: EfiMemoryType,
+    ) -> Result<&'static mut [u8]> {
+        self.read_to_end_with_progress(boot_services, memory_type, |_, _| {})
+    }
+
+    // read_to_endと同じだが、READ_CHUNK_SIZEごとに(読み込んだバイト数, 全体のバイト数)でprogressを呼ぶ
+    pub fn read_to_end_with_progress(
+        &mut self,
+        boot_services: &EfiBootServicesTable,
+        memory_type: EfiMemoryType,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<&'static mut [u8]> {
+        let position = self.position()?;
+        // SetPositionはファイルの終わりより後ろにも移動できるので、そのときは0バイトとする
+        let size = self.size()?.saturating_sub(position) as usize;
+        if size == 0 {
+            return Ok(&mut []);
+        }
+        let mut buffer = null_mut::<EfiVoid>();
+        boot_services
+            .allocate_pool(memory_type, size, &mut buffer)
+            .into_result()?;
+        let buffer = unsafe { core::slice::from_raw_parts_mut(buffer, size) };
+        let mut read = 0;
+        for chunk in buffer.chunks_mut(READ_CHUNK_SIZE) {
+            if let Err(e) = self.read_exact(chunk) {
+                let _ = boot_services.free_pool(buffer.as_mut_ptr());
+                return Err(e);
+            }
+            read += chunk.len();
+            progress(read, size);
+        }
+        Ok(buffer)
+    }
+
+    // 書き込んだバイト数を返す
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut size = buf.len();
+        self.protocol()
+            .write(&mut size, buf.as_ptr())
+            .into_result()?;
+        Ok(size)
+    }
+
+    // bufをすべて書き込む
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        for mut chunk in buf.chunks(WRITE_CHUNK_SIZE) {
+            while !chunk.is_empty() {
+                match self.write(chunk)? {
+                    0 => return Err(Error::Failed("failed to write whole buffer")),
+                    n => chunk = &chunk[n..],
                 }
             }
-            let status = self.write_char(c);
-            if status != EfiStatus::Success {
-                return status;
+        }
+        Ok(())
+    }
+
+    // 移動後の、ファイルの先頭からの位置を返す
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(position) => (position, 0),
+            SeekFrom::End(offset) => (self.size()?, offset),
+            SeekFrom::Current(offset) => (self.position()?, offset),
+        };
+        let position = base
+            .checked_add_signed(offset)
+            .ok_or(Error::Failed("seek to a negative or overflowing position"))?;
+        self.protocol().set_position(position).into_result()?;
+        Ok(position)
+    }
+
+    pub fn position(&self) -> Result<u64> {
+        let mut position = 0;
+        self.protocol().get_position(&mut position).into_result()?;
+        Ok(position)
+    }
+
+    // ファイルのバイト数。現在の位置は変わらない
+    pub fn size(&mut self) -> Result<u64> {
+        let position = self.position()?;
+        self.protocol()
+            .set_position(END_OF_FILE_POSITION)
+            .into_result()?;
+        let size = self.position()?;
+        self.protocol().set_position(position).into_result()?;
+        Ok(size)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.protocol().flush().into_result()
+    }
+
+    // ファイルの情報を取得する
+    // 名前の長さが分からないので、必要なサイズを問い合わせてから確保する
+    pub fn info<'a>(&self, boot_services: &'a EfiBootServicesTable) -> Result<FileInfo<'a>> {
+        let mut size = 0;
+        match self
+            .protocol()
+            .get_info(&EFI_FILE_INFO_GUID, &mut size, null_mut::<EfiVoid>())
+        {
+            EfiStatus::BufferTooSmall => {}
+            status => {
+                status.into_result()?;
+                return Err(Error::Failed("get_info succeeded with an empty buffer"));
             }
         }
-        EfiStatus::Success
+        let buffer = allocate_info_buffer(boot_services, size)?;
+        let status = self
+            .protocol()
+            .get_info(&EFI_FILE_INFO_GUID, &mut size, buffer);
+        if let Err(e) = status.into_result() {
+            let _ = boot_services.free_pool(buffer);
+            return Err(e);
+        }
+        FileInfo::from_pool(boot_services, buffer, size)
+    }
+
+    // ディレクトリのエントリを先頭から順に返すイテレータ。"."と".."は除く
+    pub fn read_dir<'a>(
+        &'a mut self,
+        boot_services: &'a EfiBootServicesTable,
+    ) -> Result<ReadDir<'a>> {
+        self.seek(SeekFrom::Start(0))?;
+        Ok(ReadDir {
+            dir: self,
+            boot_services,
+            done: false,
+        })
+    }
+
+    // ファイルを削除する。ハンドルは閉じられる
+    pub fn delete(self) -> Result<()> {
+        let file = ManuallyDrop::new(self);
+        match file.protocol().delete() {
+            // 削除できなかった場合は、警告として返る
+            EfiStatus::WarnDeleteFailure => Err(EfiStatus::WarnDeleteFailure.into()),
+            status => status.into_result(),
+        }
     }
 }
 
-const _: () = assert!(offset_of!(EfiSimpleFileSystemProtocol, open_volume) == 8);
-const _: () = assert!(offset_of!(EfiFileProtocol, open) == 8);
-const _: () = assert!(offset_of!(EfiFileProtocol, close) == 16);
-const _: () = assert!(offset_of!(EfiFileProtocol, write) == 40);
+fn allocate_info_buffer(boot_services: &EfiBootServicesTable, size: usize) -> Result<*mut EfiVoid> {
+    let mut buffer = null_mut::<EfiVoid>();
+    boot_services
+        .allocate_pool(EfiMemoryType::BOOT_SERVICES_DATA, size, &mut buffer)
+        .into_result()?;
+    Ok(buffer)
+}
+
+// File::read_dirが返すイテレータ
+pub struct ReadDir<'a> {
+    dir: &'a mut File,
+    boot_services: &'a EfiBootServicesTable,
+    // 一度エラーになったら、同じエラーを返し続けないように、それ以降はNoneを返す
+    done: bool,
+}
+
+impl<'a> ReadDir<'a> {
+    // 次のエントリを読む。ディレクトリの終わりではNone
+    // ディレクトリのReadは、一回に一つのEFI_FILE_INFOを返す。バッファが小さいと必要なサイズが返る
+    fn read_entry(&mut self) -> Result<Option<FileInfo<'a>>> {
+        let mut size = 0;
+        match self.dir.protocol().read(&mut size, null_mut::<EfiVoid>()) {
+            EfiStatus::BufferTooSmall => {}
+            EfiStatus::Success if size == 0 => return Ok(None),
+            status => {
+                status.into_result()?;
+                return Err(Error::Failed(
+                    "directory read succeeded with an empty buffer",
+                ));
+            }
+        }
+        let buffer = allocate_info_buffer(self.boot_services, size)?;
+        let status = self.dir.protocol().read(&mut size, buffer);
+        if let Err(e) = status.into_result() {
+            let _ = self.boot_services.free_pool(buffer);
+            return Err(e);
+        }
+        FileInfo::from_pool(self.boot_services, buffer, size).map(Some)
+    }
+}
+
+impl<'a> Iterator for ReadDir<'a> {
+    type Item = Result<FileInfo<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            match self.read_entry() {
+                Ok(Some(info))
+                    if info.file_name().eq_ignore_ascii_case(".")
+                        || info.file_name().eq_ignore_ascii_case("..") =>
+                {
+                    continue;
+                }
+                Ok(Some(info)) => return Some(Ok(info)),
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = self.protocol().close();
+    }
+}
+
+impl fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
@@ -1,11 +1,149 @@
+use crate::uefi::types::{EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID, EfiGuid, EfiStatus, EfiVoid, Result};
+use crate::uefi::{EfiBootServicesTable, Protocol};
+use core::mem::offset_of;
+use core::ptr::null_mut;
+
 // https://github.com/tianocore/edk2/blob/095bfacc9e52d5e7d4ef5e4a1c9bf311dce61b18/BaseTools/Source/C/Include/Protocol/GraphicsOutput.h#L178
 #[repr(C)]
 #[derive(Debug)]
 pub struct EfiGraphicsOutputProtocol<'a> {
-    reserved: [u64; 3],
+    query_mode: extern "win64" fn(
+        this: *const Self,
+        mode_number: u32,
+        size_of_info: *mut usize,
+        // ファームウェアがAllocatePoolで確保した領域が返るので、呼び出し側で解放する
+        info: *mut *mut EfiGraphicsOutputProtocolPixelInfo,
+    ) -> EfiStatus,
+    set_mode: extern "win64" fn(this: *const Self, mode_number: u32) -> EfiStatus,
+    blt: extern "win64" fn(
+        this: *const Self,
+        blt_buffer: *mut EfiGraphicsOutputBltPixel,
+        blt_operation: EfiGraphicsOutputBltOperation,
+        source_x: usize,
+        source_y: usize,
+        destination_x: usize,
+        destination_y: usize,
+        width: usize,
+        height: usize,
+        // blt_bufferの1行のバイト数。0のときはwidth * size_of::<EfiGraphicsOutputBltPixel>()
+        delta: usize,
+    ) -> EfiStatus,
     pub mode: &'a EfiGraphicsOutputProtocolMode<'a>,
 }
 
+unsafe impl Protocol for EfiGraphicsOutputProtocol<'_> {
+    const GUID: EfiGuid = EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID;
+}
+
+impl<'a> EfiGraphicsOutputProtocol<'a> {
+    // 指定したモードの情報を取得する
+    pub fn query_mode(
+        &self,
+        boot_services: &EfiBootServicesTable,
+        mode_number: u32,
+    ) -> Result<EfiGraphicsOutputProtocolPixelInfo> {
+        let mut size_of_info: usize = 0;
+        let mut info = null_mut::<EfiGraphicsOutputProtocolPixelInfo>();
+        (self.query_mode)(self, mode_number, &mut size_of_info, &mut info).into_result()?;
+        let mode_info = unsafe { *info };
+        let _ = boot_services.free_pool(info as *mut EfiVoid);
+        Ok(mode_info)
+    }
+
+    // 画面のモードを切り替える。フレームバッファの情報(mode)も更新される
+    pub fn set_mode(&self, mode_number: u32) -> Result<()> {
+        (self.set_mode)(self, mode_number).into_result()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn blt(
+        &self,
+        blt_buffer: *mut EfiGraphicsOutputBltPixel,
+        blt_operation: EfiGraphicsOutputBltOperation,
+        source_x: usize,
+        source_y: usize,
+        destination_x: usize,
+        destination_y: usize,
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> Result<()> {
+        (self.blt)(
+            self,
+            blt_buffer,
+            blt_operation,
+            source_x,
+            source_y,
+            destination_x,
+            destination_y,
+            width,
+            height,
+            delta,
+        )
+        .into_result()
+    }
+
+    // 使えるすべてのモードを、(モード番号, モードの情報)として返す
+    pub fn modes<'b>(
+        &'b self,
+        boot_services: &'b EfiBootServicesTable,
+    ) -> impl Iterator<Item = (u32, EfiGraphicsOutputProtocolPixelInfo)> + 'b {
+        (0..self.mode.max_mode).filter_map(move |mode_number| {
+            self.query_mode(boot_services, mode_number)
+                .ok()
+                .map(|info| (mode_number, info))
+        })
+    }
+
+    // 切り替えるモードを選ぶ
+    // preferredの解像度のモードがあればそれを、なければフレームバッファに直接書ける(RGB/BGR/PixelBitMask)
+    // モードの中で、最も大きい解像度のモードを選ぶ
+    pub fn select_mode(
+        &self,
+        boot_services: &EfiBootServicesTable,
+        preferred: Option<(u32, u32)>,
+    ) -> Option<u32> {
+        if let Some((width, height)) = preferred {
+            let found = self.modes(boot_services).find(|(_, info)| {
+                info.is_direct_color()
+                    && info.horizontal_resolution == width
+                    && info.vertical_resolution == height
+            });
+            if let Some((mode_number, _)) = found {
+                return Some(mode_number);
+            }
+        }
+        self.modes(boot_services)
+            .filter(|(_, info)| info.is_direct_color())
+            .max_by_key(|(_, info)| {
+                info.horizontal_resolution as u64 * info.vertical_resolution as u64
+            })
+            .map(|(mode_number, _)| mode_number)
+    }
+}
+
+const _: () = assert!(offset_of!(EfiGraphicsOutputProtocol, mode) == 24);
+
+// Bltで使うピクセル
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EfiGraphicsOutputBltPixel {
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+    pub reserved: u8,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EfiGraphicsOutputBltOperation {
+    // blt_bufferの先頭のピクセルの色で、画面の矩形を塗りつぶす
+    BltVideoFill = 0,
+    BltVideoToBltBuffer,
+    BltBufferToVideo,
+    BltVideoToVideo,
+}
+
 // グラフィックに関する情報を持つ構造体
 #[repr(C)]
 #[derive(Debug)]
@@ -20,17 +158,39 @@ pub struct EfiGraphicsOutputProtocolMode<'a> {
 
 // フレームバッファの情報を持つ構造体
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct EfiGraphicsOutputProtocolPixelInfo {
     version: u32,
     pub horizontal_resolution: u32,
     pub vertical_resolution: u32,
     pub pixel_format: EfiGraphicsPixelFormat,
-    _padding0: [u32; 4],
+    // pixel_formatがPixelBitMaskのときだけ有効
+    pub pixel_information: EfiPixelBitmask,
     pub pixels_per_scan_line: u32,
 }
+const _: () = assert!(offset_of!(EfiGraphicsOutputProtocolPixelInfo, pixels_per_scan_line) == 32);
+
+// PixelBitMaskのときの、32ビットのピクセル値の中での各色の位置
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EfiPixelBitmask {
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
+}
 
 impl EfiGraphicsOutputProtocolPixelInfo {
+    // フレームバッファに1ピクセル4バイトで直接書き込めるフォーマットか
+    pub fn is_direct_color(&self) -> bool {
+        matches!(
+            self.pixel_format,
+            EfiGraphicsPixelFormat::PixelRedGreenBlueReserved8BitPerColor
+                | EfiGraphicsPixelFormat::PixelBlueGreenRedReserved8BitPerColor
+                | EfiGraphicsPixelFormat::PixelBitMask
+        )
+    }
+
     pub fn get_ppixel_format(&self) -> &str {
         match self.pixel_format {
             EfiGraphicsPixelFormat::PixelRedGreenBlueReserved8BitPerColor => "PixelRGB8bit",
@@ -43,7 +203,7 @@ impl EfiGraphicsOutputProtocolPixelInfo {
 }
 
 #[repr(i32)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum EfiGraphicsPixelFormat {
     PixelRedGreenBlueReserved8BitPerColor = 0,
     PixelBlueGreenRedReserved8BitPerColor,
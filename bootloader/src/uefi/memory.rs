@@ -1,45 +1,38 @@
-#[repr(i64)]
-#[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum EfiMemoryType {
-    RESERVED = 0,
-    LOADER_CODE,
-    LOADER_DATA,
-    BOOT_SERVICES_CODE,
-    BOOT_SERVICES_DATA,
-    RUNTIME_SERVICES_CODE,
-    RUNTIME_SERVICES_DATA,
-    CONVENTIONAL_MEMORY,
-    UNUSABLE_MEMORY,
-    ACPI_RECLAIM_MEMORY,
-    ACPI_MEMORY_NVS,
-    MEMORY_MAPPED_IO,
-    MEMORY_MAPPED_IO_PORT_SPACE,
-    PAL_CODE,
-    PERSISTENT_MEMORY,
-}
+use core::mem::{offset_of, size_of};
+use core::ops::Deref;
 
-#[repr(usize)]
-pub enum EfiAllocateType {
-    AllocateAnyPages = 0,
-    AllocateMaxAddress,
-    AllocateAddress,
-    MaxAllocateType,
-}
+use boot_protocol::memory_map::MemoryDescriptor;
+// メモリマップのページの大きさ
+pub use boot_protocol::memory_map::UEFI_PAGE_SIZE;
 
-#[repr(C)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct EfiMemoryDescriptor {
-    pub memory_type: EfiMemoryType, // メモリ領域の種別
-    pub physical_start: u64,        // メモリ領域先頭の物理メモリアドレス
-    pub virtual_start: u64,         // メモリ領域先頭の仮想メモリアドレス
-    pub number_of_pages: u64,       // メモリ領域の大きさ(4KiBページ単位)
-    pub attribute: u64,             // メモリ領域が使える用途を示すビット集合
-}
+// EFI_MEMORY_TYPE
+// メモリマップには、OEM独自の値(0x70000000以上)やOSが使う値(0x80000000以上)、
+// 新しい仕様の値も入っているので、enumではなく、値をそのまま持つ構造体にする
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EfiMemoryType(pub u32);
 
-impl EfiMemoryDescriptor {
-    pub fn get_memory_type_str(&self) -> &str {
-        match self.memory_type {
+impl EfiMemoryType {
+    pub const RESERVED: EfiMemoryType = EfiMemoryType(0);
+    pub const LOADER_CODE: EfiMemoryType = EfiMemoryType(1);
+    pub const LOADER_DATA: EfiMemoryType = EfiMemoryType(2);
+    pub const BOOT_SERVICES_CODE: EfiMemoryType = EfiMemoryType(3);
+    pub const BOOT_SERVICES_DATA: EfiMemoryType = EfiMemoryType(4);
+    pub const RUNTIME_SERVICES_CODE: EfiMemoryType = EfiMemoryType(5);
+    pub const RUNTIME_SERVICES_DATA: EfiMemoryType = EfiMemoryType(6);
+    pub const CONVENTIONAL_MEMORY: EfiMemoryType = EfiMemoryType(7);
+    pub const UNUSABLE_MEMORY: EfiMemoryType = EfiMemoryType(8);
+    pub const ACPI_RECLAIM_MEMORY: EfiMemoryType = EfiMemoryType(9);
+    pub const ACPI_MEMORY_NVS: EfiMemoryType = EfiMemoryType(10);
+    pub const MEMORY_MAPPED_IO: EfiMemoryType = EfiMemoryType(11);
+    pub const MEMORY_MAPPED_IO_PORT_SPACE: EfiMemoryType = EfiMemoryType(12);
+    pub const PAL_CODE: EfiMemoryType = EfiMemoryType(13);
+    pub const PERSISTENT_MEMORY: EfiMemoryType = EfiMemoryType(14);
+    pub const UNACCEPTED_MEMORY: EfiMemoryType = EfiMemoryType(15);
+
+    // 仕様書にない値は、範囲ごとの名前にする。値そのものは呼び出し側で表示する
+    pub fn name(self) -> &'static str {
+        match self {
             EfiMemoryType::RESERVED => "RESERVED",
             EfiMemoryType::LOADER_CODE => "LOADER_CODE",
             EfiMemoryType::LOADER_DATA => "LOADER_DATA",
@@ -55,6 +48,49 @@ impl EfiMemoryDescriptor {
             EfiMemoryType::MEMORY_MAPPED_IO_PORT_SPACE => "MEMORY_MAPPED_IO_PORT_SPACE",
             EfiMemoryType::PAL_CODE => "PAL_CODE",
             EfiMemoryType::PERSISTENT_MEMORY => "PERSISTENT_MEMORY",
+            EfiMemoryType::UNACCEPTED_MEMORY => "UNACCEPTED_MEMORY",
+            EfiMemoryType(0x7000_0000..=0x7fff_ffff) => "OEM_RESERVED",
+            EfiMemoryType(0x8000_0000..) => "OS_RESERVED",
+            _ => "UNKNOWN",
         }
     }
 }
+
+#[repr(usize)]
+pub enum EfiAllocateType {
+    AllocateAnyPages = 0,
+    AllocateMaxAddress,
+    AllocateAddress,
+    MaxAllocateType,
+}
+
+// EFI_MEMORY_DESCRIPTOR
+// カーネルに渡すメモリマップの記述子と同じ型を包むので、レイアウトが食い違うことはない
+// 各フィールドは、Derefでboot_protocolのMemoryDescriptorから読む
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug)]
+pub struct EfiMemoryDescriptor(pub MemoryDescriptor);
+
+// UEFI仕様書のEFI_MEMORY_DESCRIPTORのレイアウト(Typeの後に4バイトのパディング)
+const _: () = assert!(offset_of!(MemoryDescriptor, physical_start) == 8);
+const _: () = assert!(offset_of!(MemoryDescriptor, attribute) == 32);
+const _: () = assert!(size_of::<EfiMemoryDescriptor>() == 40);
+
+impl Deref for EfiMemoryDescriptor {
+    type Target = MemoryDescriptor;
+
+    fn deref(&self) -> &MemoryDescriptor {
+        &self.0
+    }
+}
+
+impl EfiMemoryDescriptor {
+    // メモリ領域の種別
+    pub fn memory_type(&self) -> EfiMemoryType {
+        EfiMemoryType(self.0.memory_type)
+    }
+
+    pub fn get_memory_type_str(&self) -> &str {
+        self.memory_type().name()
+    }
+}
@@ -0,0 +1,47 @@
+use crate::uefi::Protocol;
+use crate::uefi::types::{EFI_RNG_PROTOCOL_GUID, EfiGuid, EfiStatus};
+use core::mem::offset_of;
+
+// https://uefi.org/specs/UEFI/2.10/37_Secure_Technologies.html#efi-rng-protocol
+#[repr(C)]
+#[derive(Debug)]
+pub struct EfiRngProtocol {
+    get_info: extern "win64" fn(
+        this: *const Self,
+        rng_algorithm_list_size: *mut usize,
+        rng_algorithm_list: *mut EfiGuid,
+    ) -> EfiStatus,
+    get_rng: extern "win64" fn(
+        this: *const Self,
+        // NULLの場合は、ファームウェアのデフォルトのアルゴリズムを使う
+        rng_algorithm: *const EfiGuid,
+        rng_value_length: usize,
+        rng_value: *mut u8,
+    ) -> EfiStatus,
+}
+
+unsafe impl Protocol for EfiRngProtocol {
+    const GUID: EfiGuid = EFI_RNG_PROTOCOL_GUID;
+}
+
+impl EfiRngProtocol {
+    // デフォルトのアルゴリズムで、bufferを乱数で埋める
+    pub fn get_rng(&self, buffer: &mut [u8]) -> EfiStatus {
+        (self.get_rng)(
+            self as *const Self,
+            core::ptr::null(),
+            buffer.len(),
+            buffer.as_mut_ptr(),
+        )
+    }
+
+    pub fn get_u64(&self) -> Option<u64> {
+        let mut buffer = [0u8; 8];
+        if self.get_rng(&mut buffer) != EfiStatus::Success {
+            return None;
+        }
+        Some(u64::from_le_bytes(buffer))
+    }
+}
+
+const _: () = assert!(offset_of!(EfiRngProtocol, get_rng) == 8);
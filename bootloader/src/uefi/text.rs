@@ -1,6 +1,63 @@
-use crate::uefi::types::{EfiHandle, EfiStatus, Result};
+use crate::uefi::types::{EfiEvent, EfiHandle, EfiStatus, Result};
 use core::fmt;
 use core::marker::PhantomPinned;
+use core::mem::offset_of;
+
+// 文字の色。set_attributeには、text_attr()で背景色と組み合わせて渡す
+pub const EFI_BLACK: usize = 0x00;
+pub const EFI_BLUE: usize = 0x01;
+pub const EFI_GREEN: usize = 0x02;
+pub const EFI_RED: usize = 0x04;
+pub const EFI_LIGHTGRAY: usize = 0x07;
+pub const EFI_YELLOW: usize = 0x0e;
+pub const EFI_WHITE: usize = 0x0f;
+
+// EFI_TEXT_ATTR(Foreground, Background)
+pub const fn text_attr(foreground: usize, background: usize) -> usize {
+    foreground | (background << 4)
+}
+
+// EFI_INPUT_KEYのスキャンコード
+pub const SCAN_NULL: u16 = 0x00;
+pub const SCAN_UP: u16 = 0x01;
+pub const SCAN_DOWN: u16 = 0x02;
+pub const SCAN_ESC: u16 = 0x17;
+
+// https://uefi.org/specs/UEFI/2.10/12_Protocols_Console_Support.html#efi-simple-text-input-protocol
+#[repr(C)]
+pub struct EfiSimpleTextInputProtocol {
+    reset: extern "win64" fn(
+        this: *const EfiSimpleTextInputProtocol,
+        extended_verification: bool,
+    ) -> EfiStatus,
+    read_key_stroke: extern "win64" fn(
+        this: *const EfiSimpleTextInputProtocol,
+        key: *mut EfiInputKey,
+    ) -> EfiStatus,
+    // キー入力があるとシグナル状態になるイベント
+    pub wait_for_key: EfiEvent,
+    _pinned: PhantomPinned,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EfiInputKey {
+    pub scan_code: u16,
+    pub unicode_char: u16,
+}
+
+impl EfiSimpleTextInputProtocol {
+    pub fn reset(&self) -> Result<()> {
+        (self.reset)(self, false).into_result()
+    }
+
+    // キー入力を一つ読む。入力がない場合はEfiStatus::NotReadyが返る
+    pub fn read_key_stroke(&self) -> Result<EfiInputKey> {
+        let mut key = EfiInputKey::default();
+        (self.read_key_stroke)(self, &mut key).into_result()?;
+        Ok(key)
+    }
+}
 
 #[repr(C)]
 pub struct EfiSimpleTextOutputProtocol {
@@ -10,17 +67,47 @@ pub struct EfiSimpleTextOutputProtocol {
     test_string: EfiHandle,
     query_mode: EfiHandle,
     set_mode: EfiHandle,
-    set_attribute: EfiHandle,
+    set_attribute:
+        extern "win64" fn(this: *const EfiSimpleTextOutputProtocol, attribute: usize) -> EfiStatus,
     clear_screen: extern "win64" fn(this: *const EfiSimpleTextOutputProtocol) -> EfiStatus,
+    set_cursor_position: extern "win64" fn(
+        this: *const EfiSimpleTextOutputProtocol,
+        column: usize,
+        row: usize,
+    ) -> EfiStatus,
+    enable_cursor:
+        extern "win64" fn(this: *const EfiSimpleTextOutputProtocol, visible: bool) -> EfiStatus,
     _pinned: PhantomPinned,
 }
 
 impl EfiSimpleTextOutputProtocol {
+    // sはNULL終端のUCS-2の文字列
+    pub fn output_string(&self, s: &[u16]) -> Result<()> {
+        debug_assert_eq!(s.last(), Some(&0));
+        (self.output_string)(self, s.as_ptr()).into_result()
+    }
+
     pub fn clear_screen(&self) -> Result<()> {
         (self.clear_screen)(self).into_result()
     }
+
+    pub fn set_attribute(&self, attribute: usize) -> Result<()> {
+        (self.set_attribute)(self, attribute).into_result()
+    }
+
+    pub fn set_cursor_position(&self, column: usize, row: usize) -> Result<()> {
+        (self.set_cursor_position)(self, column, row).into_result()
+    }
+
+    pub fn enable_cursor(&self, visible: bool) -> Result<()> {
+        (self.enable_cursor)(self, visible).into_result()
+    }
 }
 
+const _: () = assert!(offset_of!(EfiSimpleTextInputProtocol, wait_for_key) == 16);
+const _: () = assert!(offset_of!(EfiSimpleTextOutputProtocol, set_attribute) == 40);
+const _: () = assert!(offset_of!(EfiSimpleTextOutputProtocol, enable_cursor) == 64);
+
 pub struct EfiSimpleTextOutputProtocolWriter<'a> {
     pub protocol: &'a EfiSimpleTextOutputProtocol,
     //
@@ -2,6 +2,7 @@ use core::fmt;
 
 pub type EfiVoid = u8;
 pub type EfiHandle = u64;
+pub type EfiEvent = u64;
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Error {
     EfiError(EfiStatus),
@@ -13,11 +14,25 @@ impl From<EfiStatus> for Error {
         Error::EfiError(e)
     }
 }
+
+impl Error {
+    // EfiStatusとして返す必要があるときに使う。EfiStatusを持たないエラーはAbortedにする
+    pub fn status(&self) -> EfiStatus {
+        match self {
+            Error::EfiError(status) => *status,
+            Error::Failed(_) => EfiStatus::Aborted,
+        }
+    }
+}
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 // https://github.com/tianocore/edk2/blob/562bce0febd641f78df7cd61f2ed5a4c944b31ac/MdePkg/Include/Uefi/UefiSpec.h#L1351C9-L1351C58
 pub const EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL: u32 = 00000001; // OpenProtocolの属性
 
+// CreateEventのイベントの種類
+pub const EVT_TIMER: u32 = 0x80000000;
+
 pub const EFI_FILE_MODE_READ: u64 = 0x0000000000000001;
 pub const EFI_FILE_MODE_WRITE: u64 = 0x0000000000000002;
 pub const EFI_FILE_MODE_CREATE: u64 = 0x8000000000000000;
@@ -41,6 +56,24 @@ pub const EFI_LOADED_IMAGE_PROTOCOL_GUID: EfiGuid = EfiGuid {
     data2: 0x11d2,
     data3: [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
 };
+pub const EFI_RNG_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x3152bca5,
+    data1: 0xeade,
+    data2: 0x433d,
+    data3: [0x86, 0x2e, 0xc0, 0x1c, 0xdc, 0x29, 0x1f, 0x44],
+};
+pub const EFI_DEVICE_PATH_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x9576e91,
+    data1: 0x6d3f,
+    data2: 0x11d2,
+    data3: [0x8e, 0x39, 0x0, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+pub const EFI_DEVICE_PATH_TO_TEXT_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x8b843e20,
+    data1: 0x8132,
+    data2: 0x4852,
+    data3: [0x90, 0xcc, 0x55, 0x1a, 0x4e, 0x4a, 0x7f, 0x1c],
+};
 pub const EFI_FILE_INFO_GUID: EfiGuid = EfiGuid {
     data0: 0x9576e92,
     data1: 0x6d3f,
@@ -48,6 +81,33 @@ pub const EFI_FILE_INFO_GUID: EfiGuid = EfiGuid {
     data3: [0x8e, 0x39, 0x0, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
 };
 
+// configuration table GUIDs
+pub const EFI_ACPI_20_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0x8868e871,
+    data1: 0xe4f1,
+    data2: 0x11d3,
+    data3: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+};
+// ACPI 1.0のRSDP。ACPI 2.0のものがないときだけ使う
+pub const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0xeb9d2d30,
+    data1: 0x2d88,
+    data2: 0x11d3,
+    data3: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+};
+pub const SMBIOS_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0xeb9d2d31,
+    data1: 0x2d88,
+    data2: 0x11d3,
+    data3: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+};
+pub const SMBIOS3_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0xf2fd1544,
+    data1: 0x9794,
+    data2: 0x4a2c,
+    data3: [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94],
+};
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct EfiGuid {
@@ -57,29 +117,387 @@ pub struct EfiGuid {
     pub data3: [u8; 8],
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+// https://uefi.org/specs/UEFI/2.10/Apx_D_Status_Codes.html
+// エラーコードは最上位ビットが立っている。警告コードは最上位ビットが0で、1以上の値を持つ。
+pub const EFI_ERROR_BIT: usize = 1 << 63;
+
+// ファームウェアは、仕様書にない値(OEM独自のコードや新しい仕様のコード)も返すことがあるので、
+// enumではなく、値をそのまま持つ構造体にする
+#[derive(PartialEq, Eq, Copy, Clone)]
 #[must_use]
-#[repr(u64)]
-pub enum EfiStatus {
-    Success = 0,
+#[repr(transparent)]
+pub struct EfiStatus(pub usize);
+
+// 呼び出し側を変えずに済むように、仕様書の名前に合わせる
+#[allow(non_upper_case_globals)]
+impl EfiStatus {
+    pub const Success: EfiStatus = EfiStatus(0);
+    // 警告
+    pub const WarnUnknownGlyph: EfiStatus = EfiStatus(1);
+    pub const WarnDeleteFailure: EfiStatus = EfiStatus(2);
+    pub const WarnWriteFailure: EfiStatus = EfiStatus(3);
+    pub const WarnBufferTooSmall: EfiStatus = EfiStatus(4);
+    pub const WarnStaleData: EfiStatus = EfiStatus(5);
+    pub const WarnFileSystem: EfiStatus = EfiStatus(6);
+    pub const WarnResetRequired: EfiStatus = EfiStatus(7);
+    // エラー
+    pub const LoadError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 1);
+    pub const InvalidParameter: EfiStatus = EfiStatus(EFI_ERROR_BIT | 2);
+    pub const Unsupported: EfiStatus = EfiStatus(EFI_ERROR_BIT | 3);
+    pub const BadBufferSize: EfiStatus = EfiStatus(EFI_ERROR_BIT | 4);
+    pub const BufferTooSmall: EfiStatus = EfiStatus(EFI_ERROR_BIT | 5);
+    pub const NotReady: EfiStatus = EfiStatus(EFI_ERROR_BIT | 6);
+    pub const DeviceError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 7);
+    pub const WriteProtected: EfiStatus = EfiStatus(EFI_ERROR_BIT | 8);
+    pub const OutOfResources: EfiStatus = EfiStatus(EFI_ERROR_BIT | 9);
+    pub const VolumeCorrupted: EfiStatus = EfiStatus(EFI_ERROR_BIT | 10);
+    pub const VolumeFull: EfiStatus = EfiStatus(EFI_ERROR_BIT | 11);
+    pub const NoMedia: EfiStatus = EfiStatus(EFI_ERROR_BIT | 12);
+    pub const MediaChanged: EfiStatus = EfiStatus(EFI_ERROR_BIT | 13);
+    pub const NotFound: EfiStatus = EfiStatus(EFI_ERROR_BIT | 14);
+    pub const AccessDenied: EfiStatus = EfiStatus(EFI_ERROR_BIT | 15);
+    pub const NoResponse: EfiStatus = EfiStatus(EFI_ERROR_BIT | 16);
+    pub const NoMapping: EfiStatus = EfiStatus(EFI_ERROR_BIT | 17);
+    pub const Timeout: EfiStatus = EfiStatus(EFI_ERROR_BIT | 18);
+    pub const NotStarted: EfiStatus = EfiStatus(EFI_ERROR_BIT | 19);
+    pub const AlreadyStarted: EfiStatus = EfiStatus(EFI_ERROR_BIT | 20);
+    pub const Aborted: EfiStatus = EfiStatus(EFI_ERROR_BIT | 21);
+    pub const IcmpError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 22);
+    pub const TftpError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 23);
+    pub const ProtocolError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 24);
+    pub const IncompatibleVersion: EfiStatus = EfiStatus(EFI_ERROR_BIT | 25);
+    pub const SecurityViolation: EfiStatus = EfiStatus(EFI_ERROR_BIT | 26);
+    pub const CrcError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 27);
+    pub const EndOfMedia: EfiStatus = EfiStatus(EFI_ERROR_BIT | 28);
+    pub const EndOfFile: EfiStatus = EfiStatus(EFI_ERROR_BIT | 31);
+    pub const InvalidLanguage: EfiStatus = EfiStatus(EFI_ERROR_BIT | 32);
+    pub const CompromisedData: EfiStatus = EfiStatus(EFI_ERROR_BIT | 33);
+    pub const IpAddressConflict: EfiStatus = EfiStatus(EFI_ERROR_BIT | 34);
+    pub const HttpError: EfiStatus = EfiStatus(EFI_ERROR_BIT | 35);
 }
+
+// 既知のコードの名前と、UEFI仕様書に記載されている説明文
+struct KnownStatus {
+    status: EfiStatus,
+    // Debugで表示する定数名
+    ident: &'static str,
+    name: &'static str,
+    description: &'static str,
+}
+
+const KNOWN_STATUSES: &[KnownStatus] = &[
+    KnownStatus {
+        status: EfiStatus::Success,
+        ident: "Success",
+        name: "Success",
+        description: "The operation completed successfully.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnUnknownGlyph,
+        ident: "WarnUnknownGlyph",
+        name: "Warning Unknown Glyph",
+        description: "The string contained one or more characters that the device could not render and were skipped.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnDeleteFailure,
+        ident: "WarnDeleteFailure",
+        name: "Warning Delete Failure",
+        description: "The handle was closed, but the file was not deleted.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnWriteFailure,
+        ident: "WarnWriteFailure",
+        name: "Warning Write Failure",
+        description: "The handle was closed, but the data to the file was not flushed properly.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnBufferTooSmall,
+        ident: "WarnBufferTooSmall",
+        name: "Warning Buffer Too Small",
+        description: "The resulting buffer was too small, and the data was truncated to the buffer size.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnStaleData,
+        ident: "WarnStaleData",
+        name: "Warning Stale Data",
+        description: "The data has not been updated within the timeframe set by local policy for this type of data.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnFileSystem,
+        ident: "WarnFileSystem",
+        name: "Warning File System",
+        description: "The resulting buffer contains UEFI-compliant file system.",
+    },
+    KnownStatus {
+        status: EfiStatus::WarnResetRequired,
+        ident: "WarnResetRequired",
+        name: "Warning Reset Required",
+        description: "The operation will be processed across a system reset.",
+    },
+    KnownStatus {
+        status: EfiStatus::LoadError,
+        ident: "LoadError",
+        name: "Load Error",
+        description: "The image failed to load.",
+    },
+    KnownStatus {
+        status: EfiStatus::InvalidParameter,
+        ident: "InvalidParameter",
+        name: "Invalid Parameter",
+        description: "A parameter was incorrect.",
+    },
+    KnownStatus {
+        status: EfiStatus::Unsupported,
+        ident: "Unsupported",
+        name: "Unsupported",
+        description: "The operation is not supported.",
+    },
+    KnownStatus {
+        status: EfiStatus::BadBufferSize,
+        ident: "BadBufferSize",
+        name: "Bad Buffer Size",
+        description: "The buffer was not the proper size for the request.",
+    },
+    KnownStatus {
+        status: EfiStatus::BufferTooSmall,
+        ident: "BufferTooSmall",
+        name: "Buffer Too Small",
+        description: "The buffer is not large enough to hold the requested data.",
+    },
+    KnownStatus {
+        status: EfiStatus::NotReady,
+        ident: "NotReady",
+        name: "Not Ready",
+        description: "There is no data pending upon return.",
+    },
+    KnownStatus {
+        status: EfiStatus::DeviceError,
+        ident: "DeviceError",
+        name: "Device Error",
+        description: "The physical device reported an error while attempting the operation.",
+    },
+    KnownStatus {
+        status: EfiStatus::WriteProtected,
+        ident: "WriteProtected",
+        name: "Write Protected",
+        description: "The device cannot be written to.",
+    },
+    KnownStatus {
+        status: EfiStatus::OutOfResources,
+        ident: "OutOfResources",
+        name: "Out of Resources",
+        description: "A resource has run out.",
+    },
+    KnownStatus {
+        status: EfiStatus::VolumeCorrupted,
+        ident: "VolumeCorrupted",
+        name: "Volume Corrupted",
+        description: "An inconsistency was detected on the file system.",
+    },
+    KnownStatus {
+        status: EfiStatus::VolumeFull,
+        ident: "VolumeFull",
+        name: "Volume Full",
+        description: "There is no more space on the file system.",
+    },
+    KnownStatus {
+        status: EfiStatus::NoMedia,
+        ident: "NoMedia",
+        name: "No Media",
+        description: "The device does not contain any medium to perform the operation.",
+    },
+    KnownStatus {
+        status: EfiStatus::MediaChanged,
+        ident: "MediaChanged",
+        name: "Media Changed",
+        description: "The medium in the device has changed since the last access.",
+    },
+    KnownStatus {
+        status: EfiStatus::NotFound,
+        ident: "NotFound",
+        name: "Not Found",
+        description: "The item was not found.",
+    },
+    KnownStatus {
+        status: EfiStatus::AccessDenied,
+        ident: "AccessDenied",
+        name: "Access Denied",
+        description: "Access was denied.",
+    },
+    KnownStatus {
+        status: EfiStatus::NoResponse,
+        ident: "NoResponse",
+        name: "No Response",
+        description: "The server was not found or did not respond to the request.",
+    },
+    KnownStatus {
+        status: EfiStatus::NoMapping,
+        ident: "NoMapping",
+        name: "No Mapping",
+        description: "A mapping to a device does not exist.",
+    },
+    KnownStatus {
+        status: EfiStatus::Timeout,
+        ident: "Timeout",
+        name: "Timeout",
+        description: "The timeout time expired.",
+    },
+    KnownStatus {
+        status: EfiStatus::NotStarted,
+        ident: "NotStarted",
+        name: "Not Started",
+        description: "The protocol has not been started.",
+    },
+    KnownStatus {
+        status: EfiStatus::AlreadyStarted,
+        ident: "AlreadyStarted",
+        name: "Already Started",
+        description: "The protocol has already been started.",
+    },
+    KnownStatus {
+        status: EfiStatus::Aborted,
+        ident: "Aborted",
+        name: "Aborted",
+        description: "The operation was aborted.",
+    },
+    KnownStatus {
+        status: EfiStatus::IcmpError,
+        ident: "IcmpError",
+        name: "ICMP Error",
+        description: "An ICMP error occurred during the network operation.",
+    },
+    KnownStatus {
+        status: EfiStatus::TftpError,
+        ident: "TftpError",
+        name: "TFTP Error",
+        description: "A TFTP error occurred during the network operation.",
+    },
+    KnownStatus {
+        status: EfiStatus::ProtocolError,
+        ident: "ProtocolError",
+        name: "Protocol Error",
+        description: "A protocol error occurred during the network operation.",
+    },
+    KnownStatus {
+        status: EfiStatus::IncompatibleVersion,
+        ident: "IncompatibleVersion",
+        name: "Incompatible Version",
+        description: "The function encountered an internal version that was incompatible with a version requested by the caller.",
+    },
+    KnownStatus {
+        status: EfiStatus::SecurityViolation,
+        ident: "SecurityViolation",
+        name: "Security Violation",
+        description: "The function was not performed due to a security violation.",
+    },
+    KnownStatus {
+        status: EfiStatus::CrcError,
+        ident: "CrcError",
+        name: "CRC Error",
+        description: "A CRC error was detected.",
+    },
+    KnownStatus {
+        status: EfiStatus::EndOfMedia,
+        ident: "EndOfMedia",
+        name: "End of Media",
+        description: "Beginning or end of media was reached.",
+    },
+    KnownStatus {
+        status: EfiStatus::EndOfFile,
+        ident: "EndOfFile",
+        name: "End of File",
+        description: "The end of the file was reached.",
+    },
+    KnownStatus {
+        status: EfiStatus::InvalidLanguage,
+        ident: "InvalidLanguage",
+        name: "Invalid Language",
+        description: "The language specified was invalid.",
+    },
+    KnownStatus {
+        status: EfiStatus::CompromisedData,
+        ident: "CompromisedData",
+        name: "Compromised Data",
+        description: "The security status of the data is unknown or compromised and the data must be updated or replaced to restore a valid security status.",
+    },
+    KnownStatus {
+        status: EfiStatus::IpAddressConflict,
+        ident: "IpAddressConflict",
+        name: "IP Address Conflict",
+        description: "There is an address conflict address allocation.",
+    },
+    KnownStatus {
+        status: EfiStatus::HttpError,
+        ident: "HttpError",
+        name: "HTTP Error",
+        description: "A HTTP error occurred during the network operation.",
+    },
+];
+
 impl EfiStatus {
+    // 最上位ビットが立っていればエラー
+    pub fn is_error(self) -> bool {
+        self.0 & EFI_ERROR_BIT != 0
+    }
+
+    // Success以外で、最上位ビットが立っていなければ警告
+    pub fn is_warning(self) -> bool {
+        self != EfiStatus::Success && !self.is_error()
+    }
+
+    // 警告は処理自体は完了しているので、Okとして扱う
     pub fn into_result(self) -> Result<()> {
-        if self == EfiStatus::Success {
-            Ok(())
-        } else {
+        if self.is_error() {
             Err(self.into())
+        } else {
+            Ok(())
         }
     }
 
+    fn known(self) -> Option<&'static KnownStatus> {
+        KNOWN_STATUSES.iter().find(|known| known.status == self)
+    }
+
+    // 仕様書にないコードは、エラーか警告かだけを返す。値はDisplayで表示する
     pub fn to_string(self) -> &'static str {
-        match self {
-            EfiStatus::Success => "Success",
-            _ => "Unknown status",
+        match self.known() {
+            Some(known) => known.name,
+            None if self.is_error() => "Unknown Error",
+            None => "Unknown Warning",
+        }
+    }
+
+    // UEFI仕様書に記載されている説明文
+    pub fn description(self) -> &'static str {
+        match self.known() {
+            Some(known) => known.description,
+            None if self.is_error() => "The status code is not defined in the UEFI specification.",
+            None => "The warning code is not defined in the UEFI specification.",
+        }
+    }
+}
+
+impl fmt::Debug for EfiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.known() {
+            Some(known) => f.write_str(known.ident),
+            None => write!(f, "EfiStatus(0x{:X})", self.0),
         }
     }
 }
 
+impl fmt::Display for EfiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (0x{:X}): {}",
+            self.to_string(),
+            self.0,
+            self.description()
+        )
+    }
+}
+
 #[repr(C)]
 #[allow(dead_code)]
 #[derive(Default, Debug)]
@@ -104,3 +522,11 @@ pub enum EfiLocateSearchType {
     ByRegisterNotify,
     ByProtocol,
 }
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[repr(u32)]
+pub enum EfiTimerDelay {
+    TimerCancel = 0,
+    TimerPeriodic,
+    TimerRelative,
+}
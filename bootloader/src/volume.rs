@@ -0,0 +1,85 @@
+// ファイルシステムのボリュームからファイルを探す
+// SIMPLE_FILE_SYSTEM_PROTOCOLを持つハンドルをすべて列挙し、ブートボリュームを先頭にして順に探す。
+// カーネルを、ブートローダとは別のFATイメージに置けるようにする。
+use crate::uefi::device_path::{EfiDevicePathProtocol, EfiDevicePathToTextProtocol};
+use crate::uefi::file::{EfiSimpleFileSystemProtocol, File};
+use crate::uefi::types::{EFI_FILE_MODE_READ, EfiHandle, EfiStatus, Error, Result};
+use crate::uefi::{EfiLoadedImageProtocol, EfiSystemTable};
+use crate::{info, warn};
+
+// 見つかったファイルと、それがあったボリュームのルートディレクトリ
+pub struct FoundFile {
+    pub root: File,
+    pub file: File,
+}
+
+// pathのファイルを、ブートボリューム、それ以外のボリュームの順に探す
+// 探す前に、すべてのボリュームのデバイスパスをテキストにしてログ(info)に出す
+// どのボリュームにもなければ、NotFoundを返す
+pub fn find_file(
+    image_handle: EfiHandle,
+    system_table: &EfiSystemTable,
+    path: &str,
+) -> Result<FoundFile> {
+    let boot_services = system_table.boot_services;
+    let loaded_image =
+        boot_services.open_protocol::<EfiLoadedImageProtocol>(image_handle, image_handle)?;
+    let boot_device = loaded_image.device_handle;
+    let handles = boot_services.locate_handles::<EfiSimpleFileSystemProtocol>()?;
+    let volumes = handles
+        .iter()
+        .copied()
+        .filter(|&handle| handle == boot_device)
+        .chain(
+            handles
+                .iter()
+                .copied()
+                .filter(|&handle| handle != boot_device),
+        );
+
+    for (i, handle) in volumes.clone().enumerate() {
+        print_volume(image_handle, system_table, i, handle);
+    }
+
+    for (i, handle) in volumes.enumerate() {
+        let result = boot_services
+            .open_protocol::<EfiSimpleFileSystemProtocol>(handle, image_handle)
+            .and_then(File::open_volume)
+            .and_then(|root| {
+                let file = root.open(path, EFI_FILE_MODE_READ)?;
+                Ok(FoundFile { root, file })
+            });
+        match result {
+            Ok(found) => {
+                info!("  found {path} on volume {i}");
+                return Ok(found);
+            }
+            Err(Error::EfiError(EfiStatus::NotFound)) => {}
+            // 読めないボリュームがあっても、残りのボリュームを探す
+            Err(e) => warn!("  failed to open {path} on volume {i}: {e:?}"),
+        }
+    }
+    Err(Error::EfiError(EfiStatus::NotFound))
+}
+
+// ボリュームの番号とデバイスパスを表示する
+// テキストに変換できないときは、ハンドルの値を表示する
+fn print_volume(
+    image_handle: EfiHandle,
+    system_table: &EfiSystemTable,
+    index: usize,
+    handle: EfiHandle,
+) {
+    let boot_services = system_table.boot_services;
+    let text = boot_services
+        .locate_protocol::<EfiDevicePathToTextProtocol>()
+        .and_then(|to_text| {
+            let device_path =
+                boot_services.open_protocol::<EfiDevicePathProtocol>(handle, image_handle)?;
+            to_text.to_text(boot_services, device_path)
+        });
+    match text {
+        Ok(text) => info!("Volume {index}: {text}"),
+        Err(_) => info!("Volume {index}: handle 0x{handle:X}"),
+    }
+}
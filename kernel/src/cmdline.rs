@@ -0,0 +1,164 @@
+// ブートローダから渡されるカーネルのコマンドラインを解釈するモジュール
+// 空白区切りの"key=value"か"flag"の並びで、同じキーが複数回あれば後のものが優先される
+// 値に空白を含めるときは、key="a b"のようにダブルクォートで囲む
+//
+//   log=debug console=serial noscan_pci
+use boot_protocol::boot_info::MemoryRange;
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(s: &str) -> Option<LogLevel> {
+        let level = match s {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            _ => return None,
+        };
+        Some(level)
+    }
+}
+
+// カーネルのメッセージを出力する先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleKind {
+    Graphics,
+    Serial,
+}
+
+impl ConsoleKind {
+    pub fn parse(s: &str) -> Option<ConsoleKind> {
+        let console = match s {
+            "graphics" => ConsoleKind::Graphics,
+            "serial" => ConsoleKind::Serial,
+            _ => return None,
+        };
+        Some(console)
+    }
+}
+
+// コマンドラインの一つの引数。フラグの場合はvalueがNone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arg<'a> {
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cmdline<'a> {
+    text: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    pub fn new(text: &'a str) -> Cmdline<'a> {
+        Cmdline { text }
+    }
+
+    // ブートローダが渡した領域から作る。sizeには終端のNULL文字を含まない
+    pub fn from_range(range: &MemoryRange) -> Result<Cmdline<'static>, Error> {
+        if range.is_empty() {
+            return Ok(Cmdline::new(""));
+        }
+        let bytes =
+            unsafe { core::slice::from_raw_parts(range.base as *const u8, range.size as usize) };
+        let text = core::str::from_utf8(bytes).map_err(|_| Error::InvalidCmdline)?;
+        Ok(Cmdline::new(text))
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn args(&self) -> Args<'a> {
+        Args { rest: self.text }
+    }
+
+    // keyの値。"key"だけが書かれていた場合はSome("")
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.args()
+            .filter(|arg| arg.key == key)
+            .last()
+            .map(|arg| arg.value.unwrap_or(""))
+    }
+
+    pub fn has_flag(&self, key: &str) -> bool {
+        self.args().any(|arg| arg.key == key)
+    }
+
+    // log=error|warn|info|debug。指定がないか不正な値ならInfo
+    pub fn log_level(&self) -> LogLevel {
+        self.get("log")
+            .and_then(LogLevel::parse)
+            .unwrap_or(LogLevel::Info)
+    }
+
+    // console=graphics|serial。指定がないか不正な値ならGraphics
+    pub fn console(&self) -> ConsoleKind {
+        self.get("console")
+            .and_then(ConsoleKind::parse)
+            .unwrap_or(ConsoleKind::Graphics)
+    }
+
+    // noscan_pciが指定されていなければtrue
+    pub fn scan_pci(&self) -> bool {
+        !self.has_flag("noscan_pci")
+    }
+}
+
+// Cmdline::argsが返すイテレータ
+pub struct Args<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Arg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+        // ダブルクォートの中の空白では区切らない
+        let mut in_quote = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quote = !in_quote;
+                }
+                c.is_whitespace() && !in_quote
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (token, rest) = rest.split_at(end);
+        self.rest = rest;
+
+        let arg = match token.split_once('=') {
+            Some((key, value)) => Arg {
+                key,
+                value: Some(strip_quotes(value)),
+            },
+            None => Arg {
+                key: token,
+                value: None,
+            },
+        };
+        Some(arg)
+    }
+}
+
+fn strip_quotes(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
@@ -0,0 +1,64 @@
+// カーネルのログを溜めておくバッファ(dmesg)
+// 起動時にブートローダのログをコピーし、その後にカーネルのメッセージを追記する。
+// いっぱいになったら、古いものから行単位で捨てる
+use boot_protocol::boot_info::MemoryRange;
+
+pub const DMESG_BUFFER_SIZE: usize = 16 * 1024;
+
+pub struct Dmesg<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Dmesg<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Dmesg<'a> {
+        Dmesg { buffer, len: 0 }
+    }
+
+    // ブートローダが渡したログを追記する
+    pub fn import_boot_log(&mut self, range: &MemoryRange) {
+        if range.is_empty() {
+            return;
+        }
+        let log =
+            unsafe { core::slice::from_raw_parts(range.base as *const u8, range.size as usize) };
+        self.append(log);
+    }
+
+    pub fn append(&mut self, bytes: &[u8]) {
+        // バッファより長い場合は、後ろだけを残す
+        let bytes = &bytes[bytes.len().saturating_sub(self.buffer.len())..];
+        let excess = (self.len + bytes.len()).saturating_sub(self.buffer.len());
+        if excess > 0 {
+            // 行の途中から始まらないように、次の改行までまとめて捨てる
+            let discard = self.buffer[excess..self.len]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(self.len, |i| excess + i + 1);
+            self.buffer.copy_within(discard..self.len, 0);
+            self.len -= discard;
+        }
+        self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    // UTF-8として正しい部分だけを返す
+    pub fn as_str(&self) -> &str {
+        let bytes = &self.buffer[..self.len];
+        match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+        }
+    }
+
+    pub fn lines(&self) -> core::str::Lines<'_> {
+        self.as_str().lines()
+    }
+}
+
+impl core::fmt::Write for Dmesg<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.append(s.as_bytes());
+        Ok(())
+    }
+}
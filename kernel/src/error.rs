@@ -3,4 +3,6 @@ pub enum Error {
     Full,
     Empty,
     LastOfCode,
+    InvalidArchive,
+    InvalidCmdline,
 }
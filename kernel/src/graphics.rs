@@ -1,4 +1,5 @@
 use crate::font::get_font;
+use boot_protocol::boot_info::PixelBitmask;
 use core::fmt::{Result, Write};
 use core::ops::Add;
 
@@ -40,10 +41,6 @@ impl<T> Vector2D<T> {
     }
 }
 
-pub enum PixelFormat {
-    kPixelRGBResv8BitPerColor,
-    kPixelBGRResv8BitPerColor,
-}
 pub struct PixelColor {
     pub r: u8,
     pub g: u8,
@@ -216,9 +213,114 @@ impl<'a> PixelWriter<'a> for BGRResv8BitPerColorPixelWriter<'a> {
     }
 }
 
+// kPixelBitMaskのフォーマット。各色の位置はマスクで指定される
+pub struct BitmaskPixelWriter<'a> {
+    frame_buffer: &'a mut [u8],
+    pixels_per_scan_line: usize,
+    pub horizontal_resolution: usize,
+    pub vertical_resolution: usize,
+    red: ColorField,
+    green: ColorField,
+    blue: ColorField,
+}
+
+// マスクから求めた、ピクセル値の中での一つの色の位置とビット数
+#[derive(Clone, Copy)]
+struct ColorField {
+    shift: u32,
+    bits: u32,
+}
+
+impl ColorField {
+    fn from_mask(mask: u32) -> Self {
+        ColorField {
+            shift: mask.trailing_zeros() % 32,
+            bits: mask.count_ones(),
+        }
+    }
+
+    // 8ビットの値を、この色のビット数に合わせて拡大・縮小してから、位置をずらす
+    fn place(&self, value: u8) -> u32 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let value = if self.bits >= 8 {
+            (value as u32) << (self.bits - 8)
+        } else {
+            (value as u32) >> (8 - self.bits)
+        };
+        value << self.shift
+    }
+}
+
+impl<'a> BitmaskPixelWriter<'a> {
+    pub fn with_bitmask(
+        frame_buffer_base: usize,
+        pixels_per_scan_line: u32,
+        horizontal_resolution: u32,
+        vertical_resolution: u32,
+        bitmask: &PixelBitmask,
+    ) -> Self {
+        let mut writer = BitmaskPixelWriter::new(
+            frame_buffer_base,
+            pixels_per_scan_line,
+            horizontal_resolution,
+            vertical_resolution,
+        );
+        writer.red = ColorField::from_mask(bitmask.red_mask);
+        writer.green = ColorField::from_mask(bitmask.green_mask);
+        writer.blue = ColorField::from_mask(bitmask.blue_mask);
+        writer
+    }
+}
+
+impl<'a> PixelWriter<'a> for BitmaskPixelWriter<'a> {
+    // マスクを指定しない場合は、kPixelBGRResv8BitPerColorと同じ配置にする
+    fn new(
+        frame_buffer_base: usize,
+        pixels_per_scan_line: u32,
+        horizontal_resolution: u32,
+        vertical_resolution: u32,
+    ) -> Self {
+        // フレームバッファのバイト数を計算
+        let frame_buffer_size: usize =
+            (pixels_per_scan_line as usize) * (vertical_resolution as usize) * 4;
+
+        let frame_buffer = unsafe {
+            core::slice::from_raw_parts_mut(frame_buffer_base as *mut u8, frame_buffer_size)
+        };
+
+        BitmaskPixelWriter {
+            frame_buffer,
+            pixels_per_scan_line: pixels_per_scan_line as usize,
+            horizontal_resolution: horizontal_resolution as usize,
+            vertical_resolution: vertical_resolution as usize,
+            red: ColorField::from_mask(0x00ff0000),
+            green: ColorField::from_mask(0x0000ff00),
+            blue: ColorField::from_mask(0x000000ff),
+        }
+    }
+    fn horizontal_resolution(&self) -> u32 {
+        self.horizontal_resolution as u32
+    }
+    fn vertical_resolution(&self) -> u32 {
+        self.vertical_resolution as u32
+    }
+
+    fn write_no_check(&mut self, x: u32, y: u32, c: &PixelColor) -> bool {
+        let pixel_at: usize = 4 * (self.pixels_per_scan_line * (y as usize) + (x as usize));
+        let value = self.red.place(c.r) | self.green.place(c.g) | self.blue.place(c.b);
+
+        self.frame_buffer[pixel_at..pixel_at + 4].copy_from_slice(&value.to_le_bytes());
+
+        true
+    }
+}
+
 pub enum PixelWriterKind<'a> {
     RGB8(RGBResv8BitPerColorPixelWriter<'a>),
     BGR8(BGRResv8BitPerColorPixelWriter<'a>), // BGR8(BGRResv8BitPerColorPixelWriter<'a>), // 他の実装があるなら追加
+    Bitmask(BitmaskPixelWriter<'a>),
 }
 pub struct Console<'a> {
     pixel_writer: PixelWriterKind<'a>,
@@ -274,6 +376,13 @@ impl<'a> Console<'a> {
                 }
                 writer.write_no_check(x, y, &self.bg_color);
             }
+            PixelWriterKind::Bitmask(writer) => {
+                if c.is_some() {
+                    writer.write_ascii(x, y, c.unwrap(), &self.bg_color);
+                    return;
+                }
+                writer.write_no_check(x, y, &self.bg_color);
+            }
         }
     }
 
@@ -293,6 +402,13 @@ impl<'a> Console<'a> {
                 }
                 writer.write_no_check(x, y, &self.fg_color);
             }
+            PixelWriterKind::Bitmask(writer) => {
+                if c.is_some() {
+                    writer.write_ascii(x, y, c.unwrap(), &self.fg_color);
+                    return;
+                }
+                writer.write_no_check(x, y, &self.fg_color);
+            }
         }
     }
 
@@ -0,0 +1,189 @@
+// ブートローダが読み込んだinitrdを、読み込み専用のファイルシステムとして扱うモジュール
+// initrdはtar(ustar)形式のアーカイブで、ファイルの中身はアーカイブ上のバイト列をそのまま参照する
+use boot_protocol::boot_info::MemoryRange;
+
+use crate::error::Error;
+
+const BLOCK_SIZE: usize = 512;
+
+// ustarヘッダの各フィールドの位置
+const NAME: core::ops::Range<usize> = 0..100;
+const SIZE: core::ops::Range<usize> = 124..136;
+const CHECKSUM: core::ops::Range<usize> = 148..156;
+const TYPE_FLAG: usize = 156;
+const MAGIC: core::ops::Range<usize> = 257..262;
+const PREFIX: core::ops::Range<usize> = 345..500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    // シンボリックリンクなど、このファイルシステムでは扱わないもの
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TarEntry<'a> {
+    // ustarでは、長いパスはprefixとnameに分けて格納される
+    prefix: &'a str,
+    name: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+impl<'a> TarEntry<'a> {
+    // "./"や"/"で始まるパスは、それを取り除いて比べる
+    pub fn path_eq(&self, path: &str) -> bool {
+        let path = normalize(path);
+        if self.prefix.is_empty() {
+            return normalize(self.name) == path;
+        }
+        let prefix = normalize(self.prefix).trim_end_matches('/');
+        path.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|rest| rest == self.name)
+    }
+
+    pub fn prefix(&self) -> &'a str {
+        self.prefix
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+}
+
+impl core::fmt::Display for TarEntry<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.prefix.is_empty() {
+            write!(f, "{}", normalize(self.name))
+        } else {
+            write!(f, "{}/{}", normalize(self.prefix), self.name)
+        }
+    }
+}
+
+fn normalize(path: &str) -> &str {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_start_matches('/')
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TarArchive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TarArchive<'a> {
+    // 先頭のヘッダだけを検証する。壊れたエントリがあれば、イテレータはそこで終わる
+    pub fn new(data: &'a [u8]) -> Result<TarArchive<'a>, Error> {
+        if data.len() < BLOCK_SIZE {
+            return Err(Error::InvalidArchive);
+        }
+        let header = &data[..BLOCK_SIZE];
+        if !is_zero_block(header) && parse_header(header).is_none() {
+            return Err(Error::InvalidArchive);
+        }
+        Ok(TarArchive { data })
+    }
+
+    // ブートローダがinitrdを読み込んだ領域から作る
+    pub fn from_range(range: &MemoryRange) -> Result<TarArchive<'static>, Error> {
+        if range.is_empty() {
+            return Err(Error::InvalidArchive);
+        }
+        let data =
+            unsafe { core::slice::from_raw_parts(range.base as *const u8, range.size as usize) };
+        TarArchive::new(data)
+    }
+
+    pub fn entries(&self) -> TarEntries<'a> {
+        TarEntries {
+            data: self.data,
+            offset: 0,
+        }
+    }
+
+    // pathに一致する通常のファイルを探す
+    pub fn find(&self, path: &str) -> Option<TarEntry<'a>> {
+        self.entries()
+            .find(|entry| entry.kind == EntryKind::File && entry.path_eq(path))
+    }
+
+    pub fn read(&self, path: &str) -> Option<&'a [u8]> {
+        self.find(path).map(|entry| entry.data)
+    }
+}
+
+pub struct TarEntries<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for TarEntries<'a> {
+    type Item = TarEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(self.offset..self.offset + BLOCK_SIZE)?;
+        // アーカイブの終わりは、0で埋められたブロックで示される
+        if is_zero_block(header) {
+            return None;
+        }
+        let (prefix, name, kind, size) = parse_header(header)?;
+        let data_start = self.offset + BLOCK_SIZE;
+        let data = self.data.get(data_start..data_start.checked_add(size)?)?;
+        self.offset = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        Some(TarEntry {
+            prefix,
+            name,
+            kind,
+            data,
+        })
+    }
+}
+
+fn is_zero_block(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+fn parse_header(header: &[u8]) -> Option<(&str, &str, EntryKind, usize)> {
+    if &header[MAGIC] != b"ustar" {
+        return None;
+    }
+    // チェックサムは、チェックサムのフィールドを空白として計算した、ヘッダの全バイトの和
+    let checksum = parse_octal(&header[CHECKSUM])?;
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if CHECKSUM.contains(&i) { b' ' } else { b } as u64)
+        .sum();
+    if sum != checksum {
+        return None;
+    }
+    let size = parse_octal(&header[SIZE])? as usize;
+    let kind = match header[TYPE_FLAG] {
+        b'0' | 0 => EntryKind::File,
+        b'5' => EntryKind::Directory,
+        flag => EntryKind::Other(flag),
+    };
+    let name = parse_str(&header[NAME])?;
+    let prefix = parse_str(&header[PREFIX])?;
+    Some((prefix, name, kind, size))
+}
+
+// NULL文字か空白で終わる8進数
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)?.checked_add((b - b'0') as u64)?,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn parse_str(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).ok()
+}
@@ -1,7 +1,10 @@
 #![no_std]
 #![no_main]
 
+pub mod cmdline;
+pub mod dmesg;
 pub mod error;
 pub mod font;
 pub mod graphics;
+pub mod initrd;
 pub mod pci;
@@ -7,11 +7,17 @@ use core::panic::PanicInfo;
 use core::ptr::null_mut;
 use core::slice;
 use core::writeln;
+
+use boot_protocol::boot_info::{BootInfo, PixelFormat};
+use boot_protocol::memory_map::MemoryRegionKind;
+use kernel::cmdline::{Cmdline, LogLevel};
+use kernel::dmesg::{DMESG_BUFFER_SIZE, Dmesg};
 use kernel::graphics::Vector2D;
 use kernel::graphics::{
-    BGRResv8BitPerColorPixelWriter, Console, PixelColor, PixelWriter, PixelWriterKind,
-    RGBResv8BitPerColorPixelWriter,
+    BGRResv8BitPerColorPixelWriter, BitmaskPixelWriter, Console, PixelColor, PixelWriter,
+    PixelWriterKind, RGBResv8BitPerColorPixelWriter,
 };
+use kernel::initrd::{EntryKind, TarArchive};
 use kernel::pci;
 
 const MOUSE_CURSOR_WIDTH: usize = 15;
@@ -69,6 +75,7 @@ fn write_mouse(pixel_writer: &mut PixelWriterKind) {
             _write_mouse(writer);
         }
         PixelWriterKind::BGR8(writer) => _write_mouse(writer),
+        PixelWriterKind::Bitmask(writer) => _write_mouse(writer),
     }
 }
 
@@ -85,6 +92,9 @@ fn fill_rectangle(
         PixelWriterKind::BGR8(writer) => {
             writer.fill_rectangle(pos, size, color);
         }
+        PixelWriterKind::Bitmask(writer) => {
+            writer.fill_rectangle(pos, size, color);
+        }
     }
 }
 
@@ -101,36 +111,53 @@ fn draw_rectangle(
         PixelWriterKind::BGR8(writer) => {
             writer.draw_rectangle(pos, size, color);
         }
+        PixelWriterKind::Bitmask(writer) => {
+            writer.draw_rectangle(pos, size, color);
+        }
     }
 }
 
 #[unsafe(no_mangle)]
-extern "win64" fn KernelMain(
-    frame_buffer_base: usize,
-    pixels_per_scan_line: u32,
-    horizontal_resolution: u32,
-    vertical_resolution: u32,
-    pixel_format: i32,
-) -> usize {
+extern "sysv64" fn KernelMain(boot_info: *const BootInfo) -> ! {
+    // magicとversionが一致しない場合は、フレームバッファの情報も信用できないので何もせずに止める
+    // ブートローダは、'staticなLOADER_DATAに置いたBootInfoへのポインタを渡す
+    let boot_info = match unsafe { BootInfo::from_ptr(boot_info) } {
+        Ok(boot_info) => boot_info,
+        Err(_) => halt(),
+    };
+    let frame_buffer_base = boot_info.frame_buffer.frame_buffer_base as usize;
+    let pixels_per_scan_line = boot_info.frame_buffer.pixels_per_scan_line;
+    let horizontal_resolution = boot_info.frame_buffer.horizontal_resolution;
+    let vertical_resolution = boot_info.frame_buffer.vertical_resolution;
+
     // フレームバッファの配列を獲得する。フレームバッファの一つのピクセルは、u32で表現される。
     // そのため、vram_addrをu32のポインタにキャスト
-
-    let mut pixel_writer = if pixel_format == 0 {
-        PixelWriterKind::RGB8(RGBResv8BitPerColorPixelWriter::new(
-            frame_buffer_base,
-            pixels_per_scan_line,
-            horizontal_resolution,
-            vertical_resolution,
-        ))
-    } else if pixel_format == 1 {
-        PixelWriterKind::BGR8(BGRResv8BitPerColorPixelWriter::new(
+    let mut pixel_writer = match boot_info.frame_buffer.pixel_format {
+        PixelFormat::kPixelRGBResv8BitPerColor => {
+            PixelWriterKind::RGB8(RGBResv8BitPerColorPixelWriter::new(
+                frame_buffer_base,
+                pixels_per_scan_line,
+                horizontal_resolution,
+                vertical_resolution,
+            ))
+        }
+        PixelFormat::kPixelBGRResv8BitPerColor => {
+            PixelWriterKind::BGR8(BGRResv8BitPerColorPixelWriter::new(
+                frame_buffer_base,
+                pixels_per_scan_line,
+                horizontal_resolution,
+                vertical_resolution,
+            ))
+        }
+        PixelFormat::kPixelBitMask => PixelWriterKind::Bitmask(BitmaskPixelWriter::with_bitmask(
             frame_buffer_base,
             pixels_per_scan_line,
             horizontal_resolution,
             vertical_resolution,
-        ))
-    } else {
-        panic!("unimplemented color format")
+            &boot_info.frame_buffer.pixel_bitmask,
+        )),
+        // Bltはブートサービスの機能なので、カーネルからは画面に何も描けない
+        PixelFormat::kPixelBltOnly => halt(),
     };
 
     for x in 0..horizontal_resolution {
@@ -143,6 +170,9 @@ extern "win64" fn KernelMain(
                 PixelWriterKind::BGR8(writer) => {
                     writer.write_no_check(x, y, &pixel_color);
                 }
+                PixelWriterKind::Bitmask(writer) => {
+                    writer.write_no_check(x, y, &pixel_color);
+                }
             }
         }
     }
@@ -215,8 +245,99 @@ extern "win64" fn KernelMain(
     // 2. それを &mut [&mut [u8]] に変換
     let mut console = Console::new(&mut buf, desktop_fg_color, desktop_bg_color, pixel_writer);
     writeln!(console, "Welcome to MikanOS!");
+    writeln!(
+        console,
+        "Kernel: 0x{:X} - 0x{:X}, slide 0x{:X}",
+        boot_info.kernel.base,
+        boot_info.kernel.base + boot_info.kernel.size,
+        boot_info.kernel_slide
+    );
+
+    // コマンドラインを読む。壊れていた場合は、何も指定されなかったものとして扱う
+    let cmdline = Cmdline::from_range(&boot_info.cmdline).unwrap_or(Cmdline::new(""));
+    if !cmdline.as_str().is_empty() {
+        writeln!(console, "Command line: {}", cmdline.as_str());
+    }
+    if cmdline.log_level() >= LogLevel::Debug {
+        writeln!(console, "console: {:?}", cmdline.console());
+    }
+
+    // ブートローダのログをdmesgに取り込む。log=debugのときは画面にも表示する
+    let mut dmesg_buffer = [0u8; DMESG_BUFFER_SIZE];
+    let mut dmesg = Dmesg::new(&mut dmesg_buffer);
+    dmesg.import_boot_log(&boot_info.boot_log);
+    if cmdline.log_level() >= LogLevel::Debug {
+        writeln!(console, "Boot log:");
+        for line in dmesg.lines() {
+            writeln!(console, "  {}", line);
+        }
+    }
+
+    // ファームウェアのテーブルの位置を表示
+    if boot_info.acpi_rsdp == 0 {
+        writeln!(console, "ACPI RSDP not found");
+    } else {
+        writeln!(console, "ACPI RSDP: 0x{:X}", boot_info.acpi_rsdp);
+    }
+    if cmdline.log_level() >= LogLevel::Debug {
+        writeln!(
+            console,
+            "SMBIOS: 0x{:X}, SMBIOS3: 0x{:X}",
+            boot_info.smbios, boot_info.smbios3
+        );
+    }
+
+    // メモリマップの概要を表示
+    let memory_map = &boot_info.memory_map;
+    writeln!(
+        console,
+        "Memory: usable {} KiB, reclaimable {} KiB, reserved {} KiB",
+        memory_map.total_bytes(MemoryRegionKind::Usable) / 1024,
+        memory_map.total_bytes(MemoryRegionKind::Reclaimable) / 1024,
+        memory_map.total_bytes(MemoryRegionKind::Reserved) / 1024
+    );
+
+    // initrdがあれば、中のファイルを表示する
+    if !boot_info.initrd.is_empty() {
+        match TarArchive::from_range(&boot_info.initrd) {
+            Ok(initrd) => {
+                writeln!(
+                    console,
+                    "initrd: 0x{:X}, {} bytes",
+                    boot_info.initrd.base, boot_info.initrd.size
+                );
+                for entry in initrd
+                    .entries()
+                    .filter(|entry| entry.kind == EntryKind::File)
+                {
+                    writeln!(console, "  {} ({} bytes)", entry, entry.data.len());
+                }
+            }
+            Err(error) => {
+                writeln!(console, "initrd is not a valid archive: {error:?}");
+            }
+        }
+    }
 
     // PCIを読み込む
+    if cmdline.scan_pci() {
+        scan_pci_devices(&mut console);
+    } else {
+        writeln!(console, "PCI scan skipped (noscan_pci)");
+    }
+
+    // Intel製を優先してxHCを探す
+    // let mut xhc_dev: *mut pci::Device = core::ptr::null_mut();
+    // unsafe {
+    //     for i in 0..pci::NUM_DEVICES {
+    //         let dev = pci::DEVICES[i];
+    //     }
+    // }
+    halt()
+}
+
+// PCIバスを走査して、見つかったデバイスを表示する
+fn scan_pci_devices(console: &mut impl Write) {
     let res = pci::scan_all_bus();
     if let Err(error) = res {
         writeln!(console, "PCI Deivce Scan Failed. status: {error:?}");
@@ -236,14 +357,9 @@ extern "win64" fn KernelMain(
             );
         }
     }
+}
 
-    // Intel製を優先してxHCを探す
-    // let mut xhc_dev: *mut pci::Device = core::ptr::null_mut();
-    // unsafe {
-    //     for i in 0..pci::NUM_DEVICES {
-    //         let dev = pci::DEVICES[i];
-    //     }
-    // }
+fn halt() -> ! {
     loop {
         unsafe {
             asm!("hlt");